@@ -0,0 +1,3032 @@
+// The renderer itself, without any windowing: Schwarz renders frames of a
+// Schwarzschild black hole into any buffer of pixels, and the rest is what it
+// needs to get there. The blackhole_ocl binary is an SDL front end on top.
+
+extern crate image;
+extern crate ocl;
+
+use ocl::enums::{
+    DeviceInfo, DeviceInfoResult, ImageChannelDataType, ImageChannelOrder, MemObjectType,
+};
+use ocl::{Image, Kernel, ProQue, Program};
+
+/// Which OpenCL device to run on, any extra options for compiling kernels
+/// for it, and where to load the kernels' source from (None for the copies
+/// built into the binary).
+#[derive(Clone, Debug)]
+pub struct ClConfig {
+    pub platform: ocl::Platform,
+    pub device: ocl::Device,
+    pub options: String,
+    pub kernel_dir: Option<std::path::PathBuf>,
+}
+
+impl ClConfig {
+    // The source of rays.ocl.c, read fresh from kernel_dir if there is one.
+    fn rays_source(&self) -> Result<String, String> {
+        match self.kernel_dir {
+            Some(ref dir) => read_kernel_source(&dir.join("rays.ocl.c")),
+            None => Ok(include_str!("rays.ocl.c").to_string()),
+        }
+    }
+
+    // Where rays_source came from, for error messages.
+    fn rays_file(&self) -> String {
+        match self.kernel_dir {
+            Some(ref dir) => dir.join("rays.ocl.c").display().to_string(),
+            None => "the built-in rays.ocl.c".to_string(),
+        }
+    }
+
+    // A program builder for some kernel source, with our compiler options.
+    fn program_builder<'a>(&self, src: &str) -> ocl::builders::ProgramBuilder<'a> {
+        let mut builder = Program::builder();
+        builder.src(src).cmplr_opt(self.options.as_str());
+        builder
+    }
+
+    // A ProQue on our device for some kernel source. If it doesn't build,
+    // the error has the compiler's build log, under the name of the file the
+    // source came from.
+    fn pro_que<D: Into<ocl::SpatialDims>>(
+        &self,
+        filename: &str,
+        src: &str,
+        dims: D,
+    ) -> Result<ProQue, String> {
+        ProQue::builder()
+            .platform(self.platform)
+            .device(self.device)
+            .prog_bldr(self.program_builder(src))
+            .dims(dims)
+            .build()
+            .map_err(|err| {
+                format!(
+                    "Cannot build {} with options {:?}:\n{}",
+                    filename, self.options, err
+                )
+            })
+    }
+}
+
+fn read_kernel_source(path: &std::path::Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|err| format!("Cannot read {}: {}", path.display(), err))
+}
+
+// All the OpenCL platforms there are. ocl's Platform::list panics if it can't
+// get them (as on a machine without any OpenCL driver), so this asks ocl_core
+// directly.
+fn list_platforms() -> Result<Vec<ocl::Platform>, String> {
+    let ids = ocl::core::get_platform_ids().map_err(|err| {
+        format!(
+            "Cannot find any OpenCL platforms; is an OpenCL driver installed?\n{}",
+            err
+        )
+    })?;
+    Ok(ids.into_iter().map(ocl::Platform::new).collect())
+}
+
+/// Find the OpenCL platform and device with the given indices, as printed by
+/// list_devices. If they're not given, use the same defaults as ocl does.
+pub fn select_device(
+    platform: Option<usize>,
+    device: Option<usize>,
+) -> Result<(ocl::Platform, ocl::Device), String> {
+    let platforms = list_platforms()?;
+    if platforms.is_empty() {
+        return Err("No OpenCL platforms found; is an OpenCL driver installed?".to_string());
+    }
+    let platform = match platform {
+        Some(i) => *platforms.get(i).ok_or_else(|| {
+            format!(
+                "There's no OpenCL platform {}; there are {} (see --list_devices)",
+                i,
+                platforms.len()
+            )
+        })?,
+        // What ocl's Platform::default picks, except that an out of range
+        // OCL_DEFAULT_PLATFORM_IDX falls back to the first platform rather
+        // than panicking.
+        None => *platforms
+            .get(ocl::core::default_platform_idx())
+            .unwrap_or(&platforms[0]),
+    };
+
+    let devices = ocl::Device::list_all(platform).map_err(|e| e.to_string())?;
+    let device = match device {
+        Some(i) => *devices.get(i).ok_or_else(|| {
+            format!(
+                "There's no OpenCL device {} on that platform; there are {} (see --list_devices)",
+                i,
+                devices.len()
+            )
+        })?,
+        None => *devices
+            .first()
+            .ok_or_else(|| "No OpenCL devices found".to_string())?,
+    };
+
+    Ok((platform, device))
+}
+
+pub fn list_devices() -> Result<(), String> {
+    for (i, platform) in list_platforms()?.iter().enumerate() {
+        println!(
+            "Platform {}: {}",
+            i,
+            platform.name().map_err(|e| e.to_string())?
+        );
+        let devices = ocl::Device::list_all(platform).map_err(|e| e.to_string())?;
+        for (j, device) in devices.iter().enumerate() {
+            println!(
+                "  Device {}: {}",
+                j,
+                device.name().map_err(|e| e.to_string())?
+            );
+        }
+    }
+    Ok(())
+}
+
+/// GM in the kernels' units, which must match rays.ocl.c. The Schwarzschild
+/// radius is 2 * GM.
+pub const GM: f32 = 10.;
+
+/// The radius of the innermost stable circular orbit around a black hole with
+/// the given spin (as for Options::spin), in units of GM, for something
+/// orbiting anticlockwise as the disk does: 6 with no spin, down toward 1 as
+/// the spin goes to 1, and up toward 9 as it goes to -1, where the orbit is
+/// against the spin. This is kerr_isco in render.ocl.c.
+pub fn isco_radius(spin: f32) -> f32 {
+    let z1 = 1. + (1. - spin * spin).cbrt() * ((1. + spin).cbrt() + (1. - spin).cbrt());
+    let z2 = (3. * spin * spin + z1 * z1).sqrt();
+    let root = ((3. - z1) * (3. + z1 + 2. * z2)).sqrt();
+    if spin > 0. {
+        3. + z2 - root
+    } else {
+        3. + z2 + root
+    }
+}
+
+// How many points in its first orbit each ray's distance from the black hole
+// is recorded at.
+const ORBIT_STEPS: u32 = 64;
+
+// How many rays generate_outcomes_gpu traces in one go, between progress
+// reports.
+const OUTCOME_CHUNK: u32 = 1024;
+
+/// What gen_outcomes in rays.ocl.c works out about each ray.
+pub struct OutcomeTable {
+    pub angles: Vec<f32>,
+    pub outcomes: Vec<u8>,
+    /// ORBIT_STEPS per ray.
+    pub radii: Vec<f32>,
+    /// The closest each ray gets to the black hole.
+    pub min_radii: Vec<f32>,
+}
+
+// The rays in an outcome table go from min to max in num - 1 steps, so
+// there have to be at least two of them.
+fn check_num_outcomes(num: u32) -> Result<(), String> {
+    if num < 2 {
+        return Err(format!(
+            "An outcome table needs at least 2 rays; got {}",
+            num
+        ));
+    }
+    Ok(())
+}
+
+/// Since Schwarzschild black holes are spherically symmetric, there's really
+/// only one dimension that matters for rays, which is the angle between the
+/// ray and the line connecting the camera to the center of the black hole.
+/// So, precompute a list of "outcomes" for rays over a range of angles; each
+/// one tells us whether the ray falls into the black hole (and if so, at what
+/// angle around the black hole it does), or whether it escapes (and if so, at
+/// what angle). We do this with its own opencl kernel.
+///
+/// Along with the outcomes, we get ORBIT_STEPS radii for each ray, saying how
+/// far it is from the black hole at each point in its first turn around it,
+/// and how close it ever gets.
+///
+/// The rays are traced OUTCOME_CHUNK at a time, and progress is called with
+/// the fraction done after each chunk; if it returns false, generation stops
+/// there with an error.
+pub fn generate_outcomes_gpu(
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+    cl: &ClConfig,
+    progress: &mut dyn FnMut(f32) -> bool,
+) -> Result<OutcomeTable, String> {
+    check_num_outcomes(num)?;
+    let src = cl.rays_source()?;
+
+    let pro_que = cl.pro_que(&cl.rays_file(), &src, num)?;
+    let angle_buffer = pro_que.create_buffer::<f32>()?;
+    let outcome_buffer = pro_que.create_buffer::<u8>()?;
+    let radius_buffer = pro_que
+        .buffer_builder::<f32>()
+        .len(num * ORBIT_STEPS)
+        .build()?;
+    let min_radius_buffer = pro_que.create_buffer::<f32>()?;
+
+    let kernel = pro_que
+        .kernel_builder("gen_outcomes")
+        .arg(&angle_buffer)
+        .arg(&outcome_buffer)
+        .arg(min)
+        .arg(max)
+        .arg(num)
+        .arg(start_r)
+        .arg(&radius_buffer)
+        .arg(ORBIT_STEPS)
+        .arg(&min_radius_buffer)
+        .build()?;
+
+    let mut start = 0;
+    while start < num {
+        let len = OUTCOME_CHUNK.min(num - start);
+        unsafe {
+            kernel
+                .cmd()
+                .global_work_offset(start)
+                .global_work_size(len)
+                .enq()?;
+        }
+        pro_que.finish()?;
+        start += len;
+        if !progress(start as f32 / num as f32) {
+            return Err("Outcome generation cancelled".to_string());
+        }
+    }
+
+    let mut angle_vec = vec![0.0f32; angle_buffer.len()];
+    let mut outcome_vec = vec![0u8; outcome_buffer.len()];
+    let mut radius_vec = vec![0.0f32; radius_buffer.len()];
+    let mut min_radius_vec = vec![0.0f32; min_radius_buffer.len()];
+    angle_buffer.read(&mut angle_vec).enq()?;
+    outcome_buffer.read(&mut outcome_vec).enq()?;
+    radius_buffer.read(&mut radius_vec).enq()?;
+    min_radius_buffer.read(&mut min_radius_vec).enq()?;
+
+    Ok(OutcomeTable {
+        angles: angle_vec,
+        outcomes: outcome_vec,
+        radii: radius_vec,
+        min_radii: min_radius_vec,
+    })
+}
+
+// The integration constants from rays.ocl.c, for mirroring gen_outcomes on
+// the CPU; see there for what they mean.
+const NUM_ITER: u32 = 1000000;
+const TS: f32 = 0.01;
+const MIN_R: f32 = 2. * GM + 0.0001;
+const ESCAPE_R: f32 = 500.;
+
+// A ray's position and velocity in Schwarzschild coordinates, as ray_t in
+// rays.ocl.c.
+struct Ray {
+    r: f32,
+    theta: f32,
+    dr: f32,
+    dtheta: f32,
+}
+
+impl Ray {
+    // As start_ray in rays.ocl.c.
+    fn start(ray_amt: f32, start_r: f32) -> Ray {
+        let (dx, dz) = (ray_amt, 1.);
+        let r = start_r;
+        Ray {
+            r,
+            theta: std::f32::consts::PI,
+            dr: -start_r * dz / r,
+            dtheta: -start_r * dx / (r * r),
+        }
+    }
+
+    // As step_ray in rays.ocl.c: one step of Euler's method along a null
+    // geodesic.
+    fn step(&mut self) {
+        let (r, dr, dtheta) = (self.r, self.dr, self.dtheta);
+        let q = 1. - 2. * GM / r;
+        let dt = ((dr * dr / (q * q)) + r * r * dtheta * dtheta / q).sqrt();
+
+        let ddr = -GM / (r * r * r) * (r - 2. * GM) * dt * dt
+            + GM / (r * (r - 2. * GM)) * dr * dr
+            + (r - 2. * GM) * dtheta * dtheta;
+        let ddtheta = -2. / r * dtheta * dr;
+
+        self.dr += TS * ddr;
+        self.dtheta += TS * ddtheta;
+        self.r += TS * self.dr;
+        self.theta += TS * self.dtheta;
+    }
+}
+
+// The outcome of ray slot of num, as gen_outcomes in rays.ocl.c, with its
+// ORBIT_STEPS radii written into radii.
+fn trace_outcome(
+    slot: u32,
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+    radii: &mut [f32],
+) -> (f32, u8, f32) {
+    use std::f32::consts::PI;
+
+    let frac = slot as f32 / (num - 1) as f32;
+    let ray_amt = max * frac + min * (1. - frac);
+    let mut ray = Ray::start(ray_amt, start_r);
+    let escape_r = ESCAPE_R.max(2. * start_r);
+
+    let mut hit = false;
+    let mut next_radius = 0;
+    let mut min_r = ray.r;
+    for _ in 0..NUM_ITER {
+        let swept = PI - ray.theta;
+        while next_radius < radii.len()
+            && swept >= next_radius as f32 * 2. * PI / radii.len() as f32
+        {
+            radii[next_radius] = ray.r;
+            next_radius += 1;
+        }
+
+        ray.step();
+        min_r = min_r.min(ray.r);
+
+        if ray.r <= MIN_R {
+            hit = true;
+            break;
+        }
+        if ray.r > escape_r {
+            break;
+        }
+    }
+    for radius in &mut radii[next_radius..] {
+        *radius = 0.;
+    }
+
+    if hit {
+        (PI / 2. - ray.theta, 0, min_r)
+    } else {
+        let (sin, cos) = ray.theta.sin_cos();
+        let dx = ray.r * cos * ray.dtheta + sin * ray.dr;
+        let dz = -ray.r * sin * ray.dtheta + cos * ray.dr;
+        (dz.atan2(dx), 1, min_r)
+    }
+}
+
+/// The same table as generate_outcomes_gpu, integrated on the CPU instead, for
+/// checking the kernel against (or generating a table with no OpenCL device at
+/// all). It's the same Euler integration in single precision, so the two
+/// should agree closely, but not necessarily bit for bit. Rays are split
+/// between as many threads as there are cores, but this is still far slower
+/// than the GPU.
+pub fn generate_outcomes_cpu(
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+) -> Result<OutcomeTable, String> {
+    check_num_outcomes(num)?;
+    let steps = ORBIT_STEPS as usize;
+    let mut table = OutcomeTable {
+        angles: vec![0.; num as usize],
+        outcomes: vec![0; num as usize],
+        radii: vec![0.; num as usize * steps],
+        min_radii: vec![0.; num as usize],
+    };
+
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = (num as usize).div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let chunks = table
+            .angles
+            .chunks_mut(chunk)
+            .zip(table.outcomes.chunks_mut(chunk))
+            .zip(table.radii.chunks_mut(chunk * steps))
+            .zip(table.min_radii.chunks_mut(chunk));
+        for (i, (((angles, outcomes), radii), min_radii)) in chunks.enumerate() {
+            scope.spawn(move || {
+                for (j, radii) in radii.chunks_mut(steps).enumerate() {
+                    let slot = (i * chunk + j) as u32;
+                    let (angle, outcome, min_r) =
+                        trace_outcome(slot, min, max, num, start_r, radii);
+                    angles[j] = angle;
+                    outcomes[j] = outcome;
+                    min_radii[j] = min_r;
+                }
+            });
+        }
+    });
+
+    Ok(table)
+}
+
+/// Trace num rays over the same range of angles as generate_outcomes_gpu,
+/// recording num_points positions along each one, stride integration steps
+/// apart. The result is num * num_points (x, z) pairs; see trace_paths in
+/// rays.ocl.c.
+pub fn trace_paths_gpu(
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+    num_points: u32,
+    stride: u32,
+    cl: &ClConfig,
+) -> Result<Vec<f32>, String> {
+    check_num_outcomes(num)?;
+    let src = cl.rays_source()?;
+
+    let pro_que = cl.pro_que(&cl.rays_file(), &src, num)?;
+    let points_buffer = pro_que
+        .buffer_builder::<f32>()
+        .len(num * num_points * 2)
+        .build()?;
+
+    let kernel = pro_que
+        .kernel_builder("trace_paths")
+        .arg(&points_buffer)
+        .arg(min)
+        .arg(max)
+        .arg(num)
+        .arg(start_r)
+        .arg(num_points)
+        .arg(stride)
+        .build()?;
+
+    unsafe {
+        kernel.enq()?;
+    }
+
+    let mut points_vec = vec![0.0f32; points_buffer.len()];
+    points_buffer.read(&mut points_vec).enq()?;
+
+    Ok(points_vec)
+}
+
+/// Draw a top-down diagram of rays leaving the camera and bending around the
+/// black hole. The camera is at the bottom, looking up; the event horizon is
+/// filled in black and the photon sphere is drawn in grey. Rays that are
+/// captured are drawn in red, and rays that escape in blue. The rays are
+/// spread out to a bit past the ones that just graze the photon sphere, and
+/// mirrored to both sides.
+pub fn draw_trace_diagram(filename: &str, start_r: f32, cl: &ClConfig) -> Result<(), String> {
+    let size = 1024;
+    let (num_rays, num_points, stride) = (24, 4000, 25);
+
+    // Rays with an impact parameter below 3 * sqrt(3) * GM get captured.
+    let critical_b = 3. * 3f32.sqrt() * GM;
+    let max_amt = if start_r > 1.2 * critical_b {
+        1.5 * critical_b / (start_r * start_r - critical_b * critical_b).sqrt()
+    } else {
+        5.
+    };
+    let points = trace_paths_gpu(0., max_amt, num_rays, start_r, num_points, stride, cl)?;
+
+    // World coordinates covered by the diagram, in each direction from the
+    // black hole.
+    let view = 1.2 * start_r;
+    let to_pixel = |x: f32, z: f32| {
+        (
+            (x / view + 1.) / 2. * size as f32,
+            (1. - z / view) / 2. * size as f32,
+        )
+    };
+
+    let pixel_size = 2. * view / size as f32;
+    let mut img = image::RgbImage::from_fn(size, size, |x, y| {
+        let wx = (x as f32 + 0.5) * pixel_size - view;
+        let wz = view - (y as f32 + 0.5) * pixel_size;
+        let r = (wx * wx + wz * wz).sqrt();
+        if r <= 2. * GM {
+            image::Rgb([0, 0, 0])
+        } else if (r - 3. * GM).abs() < pixel_size {
+            image::Rgb([160, 160, 160])
+        } else {
+            image::Rgb([255, 255, 255])
+        }
+    });
+
+    for ray in points.chunks_exact(2 * num_points as usize) {
+        // Rays that end up near the horizon were captured.
+        let (end_x, end_z) = (ray[ray.len() - 2], ray[ray.len() - 1]);
+        let color = if (end_x * end_x + end_z * end_z).sqrt() < 3. * GM {
+            image::Rgb([220, 0, 0])
+        } else {
+            image::Rgb([0, 0, 220])
+        };
+
+        for side in &[1., -1.] {
+            for segment in ray.windows(4).step_by(2) {
+                let (x0, y0) = to_pixel(side * segment[0], segment[1]);
+                let (x1, y1) = to_pixel(side * segment[2], segment[3]);
+                let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.);
+                for i in 0..=steps as u32 {
+                    let f = i as f32 / steps;
+                    let (x, y) = (x0 + f * (x1 - x0), y0 + f * (y1 - y0));
+                    if x >= 0. && y >= 0. && x < size as f32 && y < size as f32 {
+                        img.put_pixel(x as u32, y as u32, color);
+                    }
+                }
+            }
+        }
+    }
+
+    img.save(filename)
+        .map_err(|err| format!("Cannot write {}: {}", filename, err))?;
+    println!("Wrote {}", filename);
+    Ok(())
+}
+
+// Outcome tables cached by generate_outcomes start with this, followed by a
+// format version.
+const CACHE_MAGIC: &[u8; 4] = b"BHOT";
+const CACHE_VERSION: u32 = 3;
+
+// Where to cache the outcome table for the given parameters. The file name
+// is a hash of the parameters, the ray kernel source (which holds the
+// integrator settings) and the compiler options, so changing any of them
+// misses the cache.
+fn outcome_cache_path(
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+    cl: &ClConfig,
+) -> Option<std::path::PathBuf> {
+    use std::hash::{Hash, Hasher};
+
+    let dir = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => std::path::PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (min.to_bits(), max.to_bits(), num, start_r.to_bits()).hash(&mut hasher);
+    cl.rays_source().ok()?.hash(&mut hasher);
+    cl.options.hash(&mut hasher);
+
+    Some(
+        dir.join("blackhole_ocl")
+            .join(format!("outcomes_{:016x}.bin", hasher.finish())),
+    )
+}
+
+fn outcome_cache_header(min: f32, max: f32, num: u32, start_r: f32) -> Vec<u8> {
+    let mut header = CACHE_MAGIC.to_vec();
+    for word in &[
+        CACHE_VERSION,
+        min.to_bits(),
+        max.to_bits(),
+        num,
+        start_r.to_bits(),
+    ] {
+        header.extend_from_slice(&word.to_le_bytes());
+    }
+    header
+}
+
+fn read_outcome_cache(
+    path: &std::path::Path,
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+) -> Option<OutcomeTable> {
+    let data = std::fs::read(path).ok()?;
+    let header = outcome_cache_header(min, max, num, start_r);
+
+    // The table is stored in the same order as OutcomeTable's fields.
+    let num = num as usize;
+    let num_radii = num * ORBIT_STEPS as usize;
+    if !data.starts_with(&header) || data.len() != header.len() + num * 9 + num_radii * 4 {
+        return None;
+    }
+    let (angle_bytes, rest) = data[header.len()..].split_at(num * 4);
+    let (outcomes, rest) = rest.split_at(num);
+    let (radius_bytes, min_radius_bytes) = rest.split_at(num_radii * 4);
+    let to_floats = |bytes: &[u8]| {
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    };
+
+    Some(OutcomeTable {
+        angles: to_floats(angle_bytes),
+        outcomes: outcomes.to_vec(),
+        radii: to_floats(radius_bytes),
+        min_radii: to_floats(min_radius_bytes),
+    })
+}
+
+fn write_outcome_cache(
+    path: &std::path::Path,
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+    table: &OutcomeTable,
+) -> std::io::Result<()> {
+    let mut data = outcome_cache_header(min, max, num, start_r);
+    for angle in &table.angles {
+        data.extend_from_slice(&angle.to_le_bytes());
+    }
+    data.extend_from_slice(&table.outcomes);
+    for radius in table.radii.iter().chain(&table.min_radii) {
+        data.extend_from_slice(&radius.to_le_bytes());
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, data)
+}
+
+/// A progress callback for generate_outcomes_gpu that just prints how far it's
+/// got, and never cancels.
+pub fn print_progress(done: f32) -> bool {
+    println!("Generating outcomes: {:.0}%", done * 100.);
+    true
+}
+
+/// Like generate_outcomes_gpu, but if use_cache is set, first look for a table
+/// generated with the same parameters on a previous run, and save the table
+/// for next time if there isn't one. If the GPU can't generate the table,
+/// generate_outcomes_cpu does instead.
+pub fn generate_outcomes(
+    min: f32,
+    max: f32,
+    num: u32,
+    start_r: f32,
+    cl: &ClConfig,
+    use_cache: bool,
+    progress: &mut dyn FnMut(f32) -> bool,
+) -> Result<OutcomeTable, String> {
+    let path = if use_cache {
+        outcome_cache_path(min, max, num, start_r, cl)
+    } else {
+        None
+    };
+
+    if let Some(ref path) = path {
+        if let Some(cached) = read_outcome_cache(path, min, max, num, start_r) {
+            println!("Using cached outcomes from {}", path.display());
+            return Ok(cached);
+        }
+    }
+
+    // If gen_outcomes won't build or run on the device, which can happen on
+    // devices that will still run the render kernel (it's one long loop, and
+    // some drivers kill kernels that run that long), fall back to the CPU.
+    // There's no falling back for the render kernel, so Schwarz::new still
+    // needs a device either way.
+    let mut cancelled = false;
+    let result = generate_outcomes_gpu(min, max, num, start_r, cl, &mut |done| {
+        cancelled = !progress(done);
+        !cancelled
+    });
+    let table = match result {
+        Ok(table) => table,
+        Err(err) if !cancelled => {
+            println!(
+                "Cannot generate outcomes on the OpenCL device, so generating them on the CPU instead (this is much slower):\n{}",
+                err
+            );
+            generate_outcomes_cpu(min, max, num, start_r)?
+        }
+        Err(err) => return Err(err),
+    };
+    if let Some(ref path) = path {
+        if let Err(err) = write_outcome_cache(path, min, max, num, start_r, &table) {
+            println!("Cannot cache outcomes in {}: {}", path.display(), err);
+        }
+    }
+
+    Ok(table)
+}
+
+// A texture for the render kernel: 8-bit and sRGB-encoded, from most image
+// files, or linear floats, from HDR ones.
+#[derive(Clone)]
+enum Texture {
+    Ldr(ocl::Image<u8>),
+    Hdr(ocl::Image<f32>),
+}
+
+impl Texture {
+    fn bind(&self, kernel: &Kernel, name: &'static str) -> ocl::Result<()> {
+        match *self {
+            Texture::Ldr(ref img) => kernel.set_arg(name, img),
+            Texture::Hdr(ref img) => kernel.set_arg(name, img),
+        }
+    }
+
+    fn is_hdr(&self) -> bool {
+        match *self {
+            Texture::Ldr(_) => false,
+            Texture::Hdr(_) => true,
+        }
+    }
+
+    // How much device memory the texture takes.
+    fn bytes(&self) -> u64 {
+        match *self {
+            Texture::Ldr(ref img) => img.element_count() as u64,
+            Texture::Hdr(ref img) => img.element_count() as u64 * 4,
+        }
+    }
+}
+
+fn build_image<T: ocl::OclPrm>(
+    pro_que: &ProQue,
+    data: &[T],
+    dims: (u32, u32),
+    data_type: ImageChannelDataType,
+) -> Result<ocl::Image<T>, ocl::Error> {
+    Image::<T>::builder()
+        .channel_order(ImageChannelOrder::Rgba)
+        .channel_data_type(data_type)
+        .image_type(MemObjectType::Image2d)
+        .dims(&dims)
+        .flags(
+            ocl::flags::MEM_READ_ONLY
+                | ocl::flags::MEM_HOST_WRITE_ONLY
+                | ocl::flags::MEM_COPY_HOST_PTR,
+        )
+        .copy_host_slice(data)
+        .queue(pro_que.queue().clone())
+        .build()
+}
+
+// The largest 2D image pro_que's device can hold, if it says.
+fn max_image_dims(pro_que: &ProQue) -> Option<(u32, u32)> {
+    let device = pro_que.device();
+    match (
+        device.info(DeviceInfo::Image2dMaxWidth),
+        device.info(DeviceInfo::Image2dMaxHeight),
+    ) {
+        (Ok(DeviceInfoResult::Image2dMaxWidth(w)), Ok(DeviceInfoResult::Image2dMaxHeight(h))) => {
+            Some((
+                w.min(u32::MAX as usize) as u32,
+                h.min(u32::MAX as usize) as u32,
+            ))
+        }
+        _ => None,
+    }
+}
+
+// The largest single buffer pro_que's device can allocate (which is often
+// well short of its memory), if it says.
+fn max_alloc_size(pro_que: &ProQue) -> Option<u64> {
+    match pro_que.device().info(DeviceInfo::MaxMemAllocSize) {
+        Ok(DeviceInfoResult::MaxMemAllocSize(size)) => Some(size),
+        _ => None,
+    }
+}
+
+// Print pro_que's device and its memory limits, and how much memory the
+// given allocations (a name and a size in bytes for each) take on it.
+fn print_device_usage(pro_que: &ProQue, usage: &[(&str, u64)]) {
+    let device = pro_que.device();
+    let megabytes = |bytes: u64| bytes as f64 / (1024. * 1024.);
+    println!(
+        "Device: {}",
+        device.name().unwrap_or_else(|_| "unknown".to_string())
+    );
+    if let Ok(DeviceInfoResult::GlobalMemSize(size)) = device.info(DeviceInfo::GlobalMemSize) {
+        println!("  Global memory: {:.1} MB", megabytes(size));
+    }
+    if let Some(size) = max_alloc_size(pro_que) {
+        println!("  Largest allocation: {:.1} MB", megabytes(size));
+    }
+    if let Some((w, h)) = max_image_dims(pro_que) {
+        println!("  Largest image: {}x{}", w, h);
+    }
+    for &(name, bytes) in usage {
+        println!("  {}: {:.1} MB", name, megabytes(bytes));
+    }
+    let total: u64 = usage.iter().map(|&(_, bytes)| bytes).sum();
+    println!("  Total allocated: {:.1} MB", megabytes(total));
+}
+
+// If a texture of the given size is too big for pro_que's device, the size
+// to scale it down to, keeping its shape.
+fn fit_to_device(filename: &str, pro_que: &ProQue, (w, h): (u32, u32)) -> Option<(u32, u32)> {
+    let (max_w, max_h) = max_image_dims(pro_que)?;
+    if w <= max_w && h <= max_h {
+        return None;
+    }
+    let scale = (max_w as f64 / w as f64).min(max_h as f64 / h as f64);
+    let fit = (
+        ((w as f64 * scale) as u32).clamp(1, max_w),
+        ((h as f64 * scale) as u32).clamp(1, max_h),
+    );
+    println!(
+        "{} is {}x{}, but the OpenCL device's images can be at most {}x{}; scaling it down to {}x{}",
+        filename, w, h, max_w, max_h, fit.0, fit.1
+    );
+    Some(fit)
+}
+
+// Rearrange a cubemap into the strip of faces the render kernel expects (see
+// SkyProjection).
+fn cube_strip<P: image::Pixel + 'static>(
+    filename: &str,
+    img: image::ImageBuffer<P, Vec<P::Subpixel>>,
+) -> Result<image::ImageBuffer<P, Vec<P::Subpixel>>, String> {
+    use image::{GenericImage, GenericImageView};
+
+    let (w, h) = img.dimensions();
+    if w == 6 * h {
+        return Ok(img);
+    }
+    if w % 4 != 0 || w / 4 * 3 != h {
+        return Err(format!(
+            "{} is {}x{}, but a cubemap has to be a 4:3 horizontal cross or a 6:1 strip",
+            filename, w, h
+        ));
+    }
+
+    let face = w / 4;
+    let mut strip = image::ImageBuffer::new(6 * face, face);
+    // Where front, right, back, left, up and down are in the cross, in
+    // faces across and down.
+    let cross = [(1, 1), (2, 1), (3, 1), (0, 1), (1, 0), (1, 2)];
+    for (i, &(col, row)) in cross.iter().enumerate() {
+        let view = img.view(col * face, row * face, face, face);
+        strip.copy_from(&view, i as u32 * face, 0);
+    }
+    Ok(strip)
+}
+
+fn load_image(
+    filename: &str,
+    pro_que: &ProQue,
+    projection: SkyProjection,
+) -> Result<Texture, String> {
+    let data =
+        std::fs::read(filename).map_err(|err| format!("Cannot open {}: {}", filename, err))?;
+
+    // Radiance HDR files can be brighter than white, so they keep their
+    // floats (with alpha added, since there are no three-channel images).
+    if let Ok(image::ImageFormat::HDR) = image::guess_format(&data) {
+        let decoder = image::hdr::HDRDecoder::new(&data[..])
+            .map_err(|err| format!("Cannot open {}: {}", filename, err))?;
+        let meta = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()
+            .map_err(|err| format!("Cannot open {}: {}", filename, err))?;
+        let mut rgba = Vec::with_capacity(pixels.len() * 4);
+        for p in &pixels {
+            rgba.extend_from_slice(&[p[0], p[1], p[2], 1.]);
+        }
+        println!("Loaded {} ({}x{}, HDR)", filename, meta.width, meta.height);
+        let mut full =
+            image::ImageBuffer::<image::Rgba<f32>, _>::from_raw(meta.width, meta.height, rgba)
+                .ok_or_else(|| format!("Cannot open {}: wrong number of pixels", filename))?;
+        if projection == SkyProjection::Cubemap {
+            full = cube_strip(filename, full)?;
+        }
+        let mut dims = full.dimensions();
+        if let Some((w, h)) = fit_to_device(filename, pro_que, dims) {
+            full = image::imageops::resize(&full, w, h, image::FilterType::Triangle);
+            dims = (w, h);
+        }
+        let img = build_image(pro_que, &full, dims, ImageChannelDataType::Float)?;
+        return Ok(Texture::Hdr(img));
+    }
+
+    let mut img = image::load_from_memory(&data)
+        .map_err(|err| format!("Cannot open {}: {}", filename, err.to_string()))?
+        .to_rgba();
+    let dims = img.dimensions();
+    println!("Loaded {} ({}x{})", filename, dims.0, dims.1);
+    if projection == SkyProjection::Cubemap {
+        img = cube_strip(filename, img)?;
+    }
+    let dims = img.dimensions();
+    let dims = match fit_to_device(filename, pro_que, dims) {
+        Some((w, h)) => {
+            img = image::imageops::resize(&img, w, h, image::FilterType::Triangle);
+            (w, h)
+        }
+        None => dims,
+    };
+
+    Ok(Texture::Ldr(build_image(
+        pro_que,
+        &img,
+        dims,
+        ImageChannelDataType::UnormInt8,
+    )?))
+}
+
+// An RGBA color as the kernels take it, from 0 to 1 in each channel.
+fn color_prm(color: [u8; 4]) -> ocl::prm::Float4 {
+    ocl::prm::Float4::new(
+        f32::from(color[0]) / 255.,
+        f32::from(color[1]) / 255.,
+        f32::from(color[2]) / 255.,
+        f32::from(color[3]) / 255.,
+    )
+}
+
+// A 1x1 image of a single RGBA color.
+fn solid_image(pro_que: &ProQue, color: [u8; 4]) -> Result<Texture, String> {
+    Ok(Texture::Ldr(build_image(
+        pro_que,
+        &color,
+        (1, 1),
+        ImageChannelDataType::UnormInt8,
+    )?))
+}
+
+// The bloom post-process's kernels, in the order they run.
+struct BloomKernels {
+    bright_x: Kernel,
+    blur_y: Kernel,
+    add: Kernel,
+}
+
+// Build the schwarz kernel (or the kerr kernel instead, if kerr is set), the
+// chromatic_aberration kernel too if with_post is, and the bloom kernels if
+// with_bloom is, from pro_que's program. Every argument is left as a
+// placeholder, to be set by name: Schwarz::bind_kernel_args fills in the
+// ones that stay the same from frame to frame, and Schwarz::render the rest.
+fn build_kernels(
+    pro_que: &ProQue,
+    kerr: bool,
+    with_post: bool,
+    with_bloom: bool,
+) -> ocl::Result<(Kernel, Option<Kernel>, Option<BloomKernels>)> {
+    let mut builder = pro_que.kernel_builder(if kerr { "kerr" } else { "schwarz" });
+    builder
+        .arg_named("buffer", None::<&ocl::Buffer<u8>>)
+        .arg_named("angles", None::<&ocl::Buffer<f32>>)
+        .arg_named("angle_results", None::<&ocl::Buffer<u8>>)
+        .arg_named("x_res", 0u32)
+        .arg_named("y_res", 0u32)
+        .arg_named("pitch", 0u32)
+        .arg_named("yaw", 0f32)
+        .arg_named("look_pitch", 0f32)
+        .arg_named("skytex", None::<&ocl::Image<u8>>)
+        .arg_named("skytex_b", None::<&ocl::Image<u8>>)
+        .arg_named("spheretex", None::<&ocl::Image<u8>>)
+        .arg_named("aa", 0u32)
+        .arg_named("num_outcomes", 0u32)
+        .arg_named("sky_blend", 0f32)
+        .arg_named("sky_brightness", 0f32)
+        .arg_named("ruler_spacing", 0f32)
+        .arg_named("ruler_color", ocl::prm::Float4::new(0., 0., 0., 0.))
+        .arg_named("surface_rotation", 0f32)
+        .arg_named("radii", None::<&ocl::Buffer<f32>>)
+        .arg_named("num_radii", 0u32)
+        .arg_named("disk_inner", 0f32)
+        .arg_named("disk_outer", 0f32)
+        .arg_named("disk_brightness", 0f32)
+        .arg_named("min_radii", None::<&ocl::Buffer<f32>>)
+        .arg_named("redshift", 0f32)
+        .arg_named("horizon_r", 0f32)
+        .arg_named("screen_scale", 0f32)
+        .arg_named("table_min", 0f32)
+        .arg_named("table_max", 0f32)
+        .arg_named("tonemap", 0i32)
+        .arg_named("exposure", 0f32)
+        .arg_named("srgb", 0i32)
+        .arg_named("hdr_textures", 0i32)
+        .arg_named("x_offset", 0u32)
+        .arg_named("eye_angle", 0f32)
+        .arg_named("interpolate", 0i32)
+        .arg_named("filter", 0i32)
+        .arg_named("roll", 0f32)
+        .arg_named("beaming", 0i32)
+        .arg_named("aa_pattern", 0i32)
+        .arg_named("seed", 0u32)
+        .arg_named("buffer_x", 0u32)
+        .arg_named("buffer_y", 0u32)
+        .arg_named("chromatic", 0f32)
+        .arg_named("compare", 0i32)
+        .arg_named("sky_projection", 0i32)
+        .arg_named("photon_ring", 0f32)
+        .arg_named("isco_ring", 0f32)
+        .arg_named("photon_ring_color", ocl::prm::Float4::new(0., 0., 0., 0.))
+        .arg_named("isco_ring_color", ocl::prm::Float4::new(0., 0., 0., 0.))
+        .arg_named("disktex", None::<&ocl::Image<u8>>)
+        .arg_named("disk_textured", 0i32);
+    // kerr takes all of schwarz's arguments, and these two as well.
+    if kerr {
+        builder.arg_named("spin", 0f32).arg_named("start_r", 0f32);
+    }
+    let kernel = builder.build()?;
+
+    let post_kernel = if with_post {
+        Some(
+            pro_que
+                .kernel_builder("chromatic_aberration")
+                .arg_named("src", None::<&ocl::Buffer<u8>>)
+                .arg_named("dest", None::<&ocl::Buffer<u8>>)
+                .arg_named("x_res", 0u32)
+                .arg_named("y_res", 0u32)
+                .arg_named("pitch", 0u32)
+                .arg_named("amount", 0f32)
+                .build()?,
+        )
+    } else {
+        None
+    };
+
+    let bloom_kernels = if with_bloom {
+        Some(BloomKernels {
+            bright_x: pro_que
+                .kernel_builder("bloom_bright_x")
+                .arg_named("src", None::<&ocl::Buffer<u8>>)
+                .arg_named("dest", None::<&ocl::Buffer<u8>>)
+                .arg_named("x_res", 0u32)
+                .arg_named("y_res", 0u32)
+                .arg_named("pitch", 0u32)
+                .arg_named("threshold", 0f32)
+                .arg_named("radius", 0i32)
+                .arg_named("srgb", 0i32)
+                .build()?,
+            blur_y: pro_que
+                .kernel_builder("bloom_blur_y")
+                .arg_named("src", None::<&ocl::Buffer<u8>>)
+                .arg_named("dest", None::<&ocl::Buffer<u8>>)
+                .arg_named("x_res", 0u32)
+                .arg_named("y_res", 0u32)
+                .arg_named("pitch", 0u32)
+                .arg_named("radius", 0i32)
+                .build()?,
+            add: pro_que
+                .kernel_builder("bloom_add")
+                .arg_named("bloom", None::<&ocl::Buffer<u8>>)
+                .arg_named("dest", None::<&ocl::Buffer<u8>>)
+                .arg_named("x_res", 0u32)
+                .arg_named("y_res", 0u32)
+                .arg_named("pitch", 0u32)
+                .arg_named("threshold", 0f32)
+                .arg_named("strength", 0f32)
+                .arg_named("srgb", 0i32)
+                .build()?,
+        })
+    } else {
+        None
+    };
+
+    Ok((kernel, post_kernel, bloom_kernels))
+}
+
+/// How the pixels in a frame are laid out: how many bytes each one takes
+/// up, and where the red, green and blue bytes are within it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bytes_per_pixel: usize,
+    pub offsets: [usize; 3],
+}
+
+impl PixelFormat {
+    /// Blue, green, red, then the frequency shift byte (see save_shift_map):
+    /// what the kernels write.
+    pub const BGRX: PixelFormat = PixelFormat {
+        bytes_per_pixel: 4,
+        offsets: [2, 1, 0],
+    };
+}
+
+/// A rectangle of a frame, in pixels from its top left corner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    // All of an x_res by y_res frame.
+    fn whole(x_res: u32, y_res: u32) -> Region {
+        Region {
+            x: 0,
+            y: 0,
+            width: x_res,
+            height: y_res,
+        }
+    }
+}
+
+/// Which way the camera is turned around the black hole, in radians: yaw
+/// around its poles, and pitch above or below its equator. Both 0 puts the
+/// camera on the equator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Look {
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+// For Schwarz::render_pipelined: a second buffer for frames to alternate
+// between, a queue to read the last frame back on while the next one
+// renders, and the frame that's still rendering (with its x_res, y_res and
+// pitch) if there is one.
+struct Pipeline {
+    backbuf: ocl::Buffer<u8>,
+    read_queue: ocl::Queue,
+    pending: Option<(ocl::Event, (u32, u32, u32))>,
+}
+
+// The length in bytes of an x_res by y_res frame as the kernels write it,
+// or an error if that doesn't fit in memory, in the int the kernels index
+// pixels with, or (for a row) in a u32 pitch.
+fn frame_len(x_res: u32, y_res: u32) -> Result<usize, String> {
+    let pixels = (x_res as usize)
+        .checked_mul(y_res as usize)
+        .filter(|&pixels| pixels <= i32::MAX as usize && x_res.checked_mul(4).is_some());
+    pixels
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or_else(|| format!("A {}x{} frame is too big to render", x_res, y_res))
+}
+
+// A buffer for an x_res by y_res frame, checked against the largest single
+// allocation the device allows.
+fn frame_buffer(pro_que: &ProQue, x_res: u32, y_res: u32) -> Result<ocl::Buffer<u8>, String> {
+    let len = frame_len(x_res, y_res)?;
+    if let Some(max) = max_alloc_size(pro_que) {
+        if len as u64 > max {
+            return Err(format!(
+                "A {}x{} frame takes {} bytes, but the OpenCL device can only allocate {} at a time",
+                x_res, y_res, len, max
+            ));
+        }
+    }
+    Ok(pro_que.buffer_builder().len(len).build()?)
+}
+
+// The buffers for rendering x_res by y_res frames: the one they end up in,
+// and, if there's a post-process, the one the main kernel renders into
+// first.
+fn frame_buffers(
+    pro_que: &ProQue,
+    x_res: u32,
+    y_res: u32,
+    post: bool,
+) -> Result<(ocl::Buffer<u8>, Option<ocl::Buffer<u8>>), String> {
+    let dest = frame_buffer(pro_que, x_res, y_res)?;
+    let post = if post {
+        Some(frame_buffer(pro_que, x_res, y_res)?)
+    } else {
+        None
+    };
+    Ok((dest, post))
+}
+
+// The pair of buffers the bloom post-process blurs through.
+type BloomBuffers = (ocl::Buffer<u8>, ocl::Buffer<u8>);
+
+// Bloom's buffers for x_res by y_res frames, if it's on.
+fn bloom_buffers(
+    pro_que: &ProQue,
+    x_res: u32,
+    y_res: u32,
+    bloom: bool,
+) -> Result<Option<BloomBuffers>, String> {
+    if !bloom {
+        return Ok(None);
+    }
+    Ok(Some((
+        frame_buffer(pro_que, x_res, y_res)?,
+        frame_buffer(pro_que, x_res, y_res)?,
+    )))
+}
+
+// Whether the kernels can write a frame straight into a destination with
+// this pitch and format. That needs the kernels' own layout, BGRX without
+// padding, since the buffers they write into are only sized for that;
+// anything else gets rearranged by read_frame.
+fn writes_directly(x_res: u32, pitch: u32, format: PixelFormat) -> bool {
+    format == PixelFormat::BGRX && pitch as u64 == x_res as u64 * 4
+}
+
+// Read the given rows of a frame the kernels rendered into buf into the
+// same rows of dest, whose pitch is in bytes, rearranging them into format
+// if need be. See Schwarz::render for what pitch and format are. The
+// read goes on queue, once wait (if given) has finished.
+#[allow(clippy::too_many_arguments)]
+fn read_frame(
+    buf: &ocl::Buffer<u8>,
+    queue: &ocl::Queue,
+    wait: Option<&ocl::Event>,
+    dest: &mut [u8],
+    x_res: u32,
+    rows: std::ops::Range<u32>,
+    pitch: u32,
+    format: PixelFormat,
+) -> Result<(), String> {
+    // I expect this isn't the "proper" way to do this (it looks like surface
+    // access isn't the preferred way to use SDL in rust in general), but it
+    // works well enough...
+    if writes_directly(x_res, pitch, format) {
+        let start = pitch as usize * rows.start as usize;
+        let end = pitch as usize * rows.end as usize;
+        buf.read(&mut dest[start..end])
+            .offset(start)
+            .queue(queue)
+            .ewait(wait)
+            .enq()?;
+        return Ok(());
+    }
+
+    let row_len = x_res as usize * 4;
+    let mut frame = vec![0u8; row_len * rows.len()];
+    buf.read(&mut frame)
+        .offset(row_len * rows.start as usize)
+        .queue(queue)
+        .ewait(wait)
+        .enq()?;
+    let [r, g, b] = format.offsets;
+    for (src_row, dest_row) in frame
+        .chunks_exact(row_len)
+        .zip(dest.chunks_mut(pitch as usize).skip(rows.start as usize))
+    {
+        for (p, d) in src_row
+            .chunks_exact(4)
+            .zip(dest_row.chunks_exact_mut(format.bytes_per_pixel))
+        {
+            d[r] = p[2];
+            d[g] = p[1];
+            d[b] = p[0];
+        }
+    }
+    Ok(())
+}
+
+/// Tone curves for bringing a sky brighter than the texture's range back
+/// into it, rather than clipping. The values are what the render kernel's
+/// tonemap argument expects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    None = 0,
+    Reinhard = 1,
+    Aces = 2,
+}
+
+/// Where the antialiasing rays go within each pixel; see subsample_offset
+/// in render.ocl.c. The values are what the render kernel's aa_pattern
+/// argument expects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AaPattern {
+    Grid = 0,
+    Rotated = 1,
+    Jittered = 2,
+}
+
+/// How sky textures map onto the sky; see sky_coords in render.ocl.c. The
+/// values are what the render kernel's sky_projection argument expects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkyProjection {
+    Equirect = 0,
+    /// Loaded from a 4:3 horizontal cross, or a 6:1 strip of faces in the
+    /// order cube_coords in render.ocl.c expects.
+    Cubemap = 1,
+}
+
+/// Settings for Schwarz::new, mostly straight from the command line.
+pub struct Options<'a> {
+    /// Antialiasing factor: each pixel gets aa * aa rays, so this has to be at
+    /// least 1, and render time goes up with its square.
+    pub aa: u32,
+    pub aa_pattern: AaPattern,
+    /// Seed for AaPattern::Jittered's per-pixel shifts. Renders with the
+    /// same seed come out the same.
+    pub seed: u64,
+    pub num_outcomes: u32,
+    pub x_res: u32,
+    pub y_res: u32,
+    pub skybox_file: &'a str,
+    pub skybox_b_file: Option<&'a str>,
+    /// How both sky textures are laid out.
+    pub sky_projection: SkyProjection,
+    pub surface_file: Option<&'a str>,
+    pub render_kernel_file: Option<&'a str>,
+    pub sky_brightness: f32,
+    /// Spacing of the ruler overlay's rings, in degrees; 0 turns it off.
+    pub ruler_spacing: f32,
+    pub ruler_color: [u8; 4],
+    /// Whether to overlay rings where the photon sphere (1.5 Schwarzschild
+    /// radii) and innermost stable circular orbit (3) would appear if light
+    /// travelled in straight lines, and their colors.
+    pub show_orbits: bool,
+    pub photon_sphere_color: [u8; 4],
+    pub isco_color: [u8; 4],
+    /// Distance from the camera to the center of the black hole, in the
+    /// kernels' units.
+    pub start_r: f32,
+    /// Whether to cache the outcome table on disk between runs.
+    pub cache_outcomes: bool,
+    /// Rotation of the event horizon texture around the poles, in degrees.
+    pub surface_rotation: f32,
+    /// Color for rays that fall into the hole, if there's no surface_file.
+    pub horizon_color: [u8; 4],
+    /// Strength of the chromatic aberration post-process; 0 turns it off.
+    pub chromatic_aberration: f32,
+    /// Strength of the bloom post-process (0 turns it off), the brightness,
+    /// from 0 up to but not including 1, above which light blooms, and how
+    /// far it spreads, in pixels.
+    pub bloom: f32,
+    pub bloom_threshold: f32,
+    pub bloom_radius: u32,
+    /// Inner and outer radius of the accretion disk in the kernels' units
+    /// (both 0 for no disk), and a multiplier for its brightness.
+    pub disk_inner: f32,
+    pub disk_outer: f32,
+    pub disk_brightness: f32,
+    /// Texture for the disk, in place of its temperature ramp (see
+    /// disk_texture_color in render.ocl.c for how it's laid out).
+    pub disk_file: Option<&'a str>,
+    /// Whether to Doppler shift the disk's light by its orbital motion,
+    /// brightening and bluing the side coming toward the camera.
+    pub beaming: bool,
+    /// How strongly to redden the sky where rays pass close to the hole; 0
+    /// turns it off.
+    pub redshift: f32,
+    /// How much more than green the sky's red is bent by the hole, and how
+    /// much less its blue, as a fraction of the deflection; 0, as in real
+    /// life, bends them all alike.
+    pub chromatic: f32,
+    /// Tone curve for the sky, and how much to scale its brightness (in
+    /// linear light, along with sky_brightness) before applying it. Exposure
+    /// only matters if there's a curve.
+    pub tonemap: ToneMap,
+    pub exposure: f32,
+    /// Whether textures and frames are sRGB-encoded, and so get converted to
+    /// and from linear light for blending. If not, they're used as is.
+    pub srgb: bool,
+    /// For stereo, the distance between the eyes in the kernels' units, or 0
+    /// for a single view. See Schwarz::enqueue_kernels.
+    pub eye_separation: f32,
+    /// Whether to interpolate between outcome table entries, rather than
+    /// taking the nearest one.
+    pub interpolate_outcomes: bool,
+    /// Whether to filter textures bilinearly, rather than taking the nearest
+    /// texel.
+    pub filter_textures: bool,
+    /// Whether to show the sky as it would look with no black hole on the
+    /// left half of the frame, for comparison with the right. Not for
+    /// stereo.
+    pub compare: bool,
+    /// How fast the black hole spins, as a fraction of the most it could
+    /// for its mass, so between -1 and 1 (exclusive); positive is
+    /// anticlockwise seen from above the north pole. 0 is a Schwarzschild
+    /// black hole, drawn from the outcome table. Anything else is drawn by
+    /// the kerr kernel, which traces every ray through the Kerr metric
+    /// instead: many times slower, and without stereo, the comparison view,
+    /// redshift, beaming, chromatic or the orbit rings.
+    pub spin: f32,
+    /// Whether to print the device's memory limits, and how much of its
+    /// memory the frame buffers, outcome table and textures take.
+    pub verbose: bool,
+    /// Horizontal field of view, in degrees.
+    pub fov: f32,
+    /// Rotation of the camera about its view axis, clockwise, in degrees.
+    pub roll: f32,
+    /// Range of rays to compute outcomes for, as the tangents of their angles
+    /// from the line between the camera and the black hole.
+    pub table_min: f32,
+    pub table_max: f32,
+    /// OpenCL device and compiler options.
+    pub cl: &'a ClConfig,
+    /// More devices to share the work of render with, each taking a band of
+    /// rows (see Schwarz::render); empty to render on cl's device alone.
+    pub split_devices: &'a [ClConfig],
+}
+
+impl<'a> Options<'a> {
+    /// The command line's defaults, for a width by height frame of the sky
+    /// in skybox_file on cl's device: the camera 5 Schwarzschild radii out,
+    /// 4x antialiasing, no disk or post-processing, and the outcome table
+    /// cached.
+    pub fn new(skybox_file: &'a str, x_res: u32, y_res: u32, cl: &'a ClConfig) -> Options<'a> {
+        Options {
+            aa: 4,
+            aa_pattern: AaPattern::Grid,
+            seed: 0,
+            num_outcomes: 8192,
+            x_res,
+            y_res,
+            skybox_file,
+            skybox_b_file: None,
+            sky_projection: SkyProjection::Equirect,
+            surface_file: None,
+            render_kernel_file: None,
+            sky_brightness: 1.,
+            ruler_spacing: 0.,
+            ruler_color: [255, 255, 255, 128],
+            show_orbits: false,
+            photon_sphere_color: [255, 160, 0, 255],
+            isco_color: [64, 192, 255, 255],
+            start_r: 10. * GM,
+            cache_outcomes: true,
+            surface_rotation: 0.,
+            horizon_color: [0, 0, 0, 255],
+            chromatic_aberration: 0.,
+            bloom: 0.,
+            bloom_threshold: 0.8,
+            bloom_radius: 8,
+            disk_inner: 0.,
+            disk_outer: 0.,
+            disk_brightness: 1.,
+            disk_file: None,
+            beaming: false,
+            redshift: 0.,
+            chromatic: 0.,
+            tonemap: ToneMap::None,
+            exposure: 1.,
+            srgb: true,
+            eye_separation: 0.,
+            interpolate_outcomes: true,
+            filter_textures: true,
+            compare: false,
+            spin: 0.,
+            verbose: false,
+            // Wide enough that the edges of the screen are rays three times
+            // as far out as they are forward.
+            fov: 2. * 3f32.atan().to_degrees(),
+            roll: 0.,
+            table_min: 0.,
+            table_max: 5.,
+            cl,
+            split_devices: &[],
+        }
+    }
+}
+
+/// Everything we need to keep track of.
+pub struct Schwarz {
+    // The OpenCL state
+    pro_que: ProQue,
+    // The schwarz kernel, the chromatic_aberration kernel if postbuf is in
+    // use, and the bloom kernels if bloombufs are. See build_kernels.
+    kernel: Kernel,
+    post_kernel: Option<Kernel>,
+    bloom_kernels: Option<BloomKernels>,
+    // Angle buffer
+    angles: ocl::Buffer<f32>,
+    // Buffer to render into
+    destbuf: ocl::Buffer<u8>,
+    // The second buffer and the frame in flight, for render_pipelined. Set
+    // up the first time it's used.
+    pipeline: Option<Pipeline>,
+    // "result" buffer--whether a ray falls in or escapes
+    angle_result: ocl::Buffer<u8>,
+    // ORBIT_STEPS radii for each ray, for finding where it crosses the
+    // accretion disk.
+    radii: ocl::Buffer<f32>,
+    // How close each ray gets to the black hole, for redshift.
+    min_radii: ocl::Buffer<f32>,
+    // Sky texture
+    skytex: Texture,
+    // Second sky texture, cross-faded with the first by sky_blend. This is
+    // just skytex again if no second sky was given.
+    skytex_b: Texture,
+    // Event horizon texture
+    spheretex: Texture,
+    // If spheretex came from a file, the solid horizon color to show in its
+    // place while show_surface is off; see toggle_surface.
+    plain_spheretex: Option<Texture>,
+    show_surface: bool,
+    // Accretion disk texture, if there's a disk_file.
+    disktex: Option<Texture>,
+    // Antialias factor. Applies to each dimension--so the number of rays
+    // per pixel is the *square* of this.
+    aa: u32,
+    aa_pattern: AaPattern,
+    // Layout of the sky textures.
+    sky_projection: SkyProjection,
+    seed: u64,
+    // Length of the angles and angle_result buffers
+    num_outcomes: u32,
+    /// How much of skytex_b to mix in: 0 is all skytex, 1 is all skytex_b.
+    pub sky_blend: f32,
+    // Multiplier for the sampled sky color.
+    sky_brightness: f32,
+    // Where the render kernel source was loaded from (Options'
+    // render_kernel_file, or render.ocl.c in the ClConfig's kernel_dir), if
+    // not built in.
+    render_kernel_file: Option<std::path::PathBuf>,
+    // Spacing of the ruler overlay's rings in radians (0 = no ruler), and
+    // its RGBA color.
+    ruler_spacing: f32,
+    ruler_color: ocl::prm::Float4,
+    // Whether to draw the orbit rings, and their RGBA colors.
+    show_orbits: bool,
+    photon_sphere_color: ocl::prm::Float4,
+    isco_color: ocl::prm::Float4,
+    // Rotation of spheretex around the poles, in radians.
+    surface_rotation: f32,
+    // Strength of the chromatic aberration post-process, and the buffer the
+    // main kernel renders into when it's on (destbuf then gets the result of
+    // the post-process).
+    chromatic_aberration: f32,
+    postbuf: Option<ocl::Buffer<u8>>,
+    // Bloom strength (0 for none), threshold and radius, and the buffers it
+    // blurs through: the first gets the bright part of destbuf blurred
+    // across, and the second that blurred down. It runs after chromatic
+    // aberration, adding back onto destbuf.
+    bloom: f32,
+    bloom_threshold: f32,
+    bloom_radius: u32,
+    bloombufs: Option<BloomBuffers>,
+    // Accretion disk radii (both 0 for no disk) and brightness.
+    disk_inner: f32,
+    disk_outer: f32,
+    disk_brightness: f32,
+    // Whether to Doppler shift the disk.
+    beaming: bool,
+    // Strength of the redshift tint (0 for none).
+    redshift: f32,
+    // Strength of the per-color bending (0 for none).
+    chromatic: f32,
+    // Tone curve and exposure for the sky.
+    tonemap: ToneMap,
+    exposure: f32,
+    // Whether to convert textures and frames from and to sRGB.
+    srgb: bool,
+    // Distance between the eyes for stereo, or 0 for a single view.
+    eye_separation: f32,
+    // Whether to interpolate between outcome table entries.
+    interpolate_outcomes: bool,
+    // Whether to filter textures bilinearly.
+    filter_textures: bool,
+    // Whether the left half is unlensed.
+    compare: bool,
+    // Spin as a fraction of the maximum; 0 for the outcome table.
+    spin: f32,
+    /// Horizontal field of view, in radians.
+    pub fov: f32,
+    /// Clockwise rotation about the view axis, in radians.
+    pub roll: f32,
+    // Range of rays the outcome table covers (see Options).
+    table_min: f32,
+    table_max: f32,
+    // OpenCL device and compiler options, for reload_kernel.
+    cl: ClConfig,
+    // Distance from the camera to the black hole that the outcome table was
+    // generated for, in the kernels' units, and whether to cache tables.
+    start_r: f32,
+    cache_outcomes: bool,
+    // One of these for each of Options' split_devices, rendering its own
+    // band of each frame for render. They get the same outcome table as
+    // this one, and take their camera settings (fov and so on) from it
+    // rather than their own.
+    helpers: Vec<Schwarz>,
+}
+
+/// The same two types under the names to embed the renderer by: build a
+/// BlackHoleRenderer from a RenderConfig with BlackHoleRenderer::new, then
+/// draw each frame into a buffer of your own with render.
+pub type BlackHoleRenderer = Schwarz;
+pub type RenderConfig<'a> = Options<'a>;
+
+impl Schwarz {
+    /// Set up a renderer, loading the textures and generating the outcome
+    /// table. The table is slow, so progress hears how it's going as for
+    /// generate_outcomes_gpu, and can cancel it, which makes this fail.
+    pub fn new(opts: &Options, progress: &mut dyn FnMut(f32) -> bool) -> Result<Schwarz, String> {
+        if opts.aa == 0 {
+            return Err("The antialiasing factor must be at least 1".to_string());
+        }
+        if opts.compare && opts.eye_separation > 0. {
+            return Err("The comparison view can't be shown in stereo".to_string());
+        }
+        if !(opts.spin > -1. && opts.spin < 1.) {
+            return Err(format!(
+                "The spin must be between -1 and 1, not including them; got {}",
+                opts.spin
+            ));
+        }
+        if opts.spin != 0. {
+            let unsupported = [
+                (opts.eye_separation > 0., "stereo"),
+                (opts.compare, "the comparison view"),
+                (opts.redshift > 0., "redshift"),
+                (opts.beaming, "beaming"),
+                (opts.chromatic != 0., "chromatic lensing"),
+                (opts.show_orbits, "the orbit rings"),
+            ];
+            if let Some((_, name)) = unsupported.iter().find(|(on, _)| *on) {
+                return Err(format!(
+                    "A spinning black hole can't be drawn with {}",
+                    name
+                ));
+            }
+        }
+        if !(0. ..1.).contains(&opts.bloom_threshold) {
+            return Err("The bloom threshold must be at least 0 and less than 1".to_string());
+        }
+        if !opts.split_devices.is_empty() && (opts.chromatic_aberration > 0. || opts.bloom > 0.) {
+            // The post-processes sample neighboring pixels, which could be
+            // in another device's band.
+            return Err("Chromatic aberration and bloom can't be split across devices".to_string());
+        }
+
+        let mut schwarz = Schwarz::on_device(opts, None, progress)?;
+        if !opts.split_devices.is_empty() {
+            // The kerr kernel doesn't use a table, so there's none to share.
+            let table = if opts.spin == 0. {
+                Some(schwarz.read_table()?)
+            } else {
+                None
+            };
+            for cl in opts.split_devices {
+                let helper_opts = Options {
+                    cl,
+                    split_devices: &[],
+                    ..*opts
+                };
+                schwarz
+                    .helpers
+                    .push(Schwarz::on_device(&helper_opts, table.as_ref(), progress)?);
+            }
+        }
+        Ok(schwarz)
+    }
+
+    // Set up on opts.cl's device alone, with the given outcome table, or a
+    // new one if there isn't one (and it isn't spinning, so needs one),
+    // generated with progress.
+    fn on_device(
+        opts: &Options,
+        table: Option<&OutcomeTable>,
+        progress: &mut dyn FnMut(f32) -> bool,
+    ) -> Result<Schwarz, String> {
+        let Options {
+            aa,
+            aa_pattern,
+            seed,
+            num_outcomes,
+            x_res,
+            y_res,
+            skybox_file,
+            skybox_b_file,
+            sky_projection,
+            surface_file,
+            render_kernel_file,
+            sky_brightness,
+            ruler_spacing,
+            ruler_color,
+            show_orbits,
+            photon_sphere_color,
+            isco_color,
+            start_r,
+            cache_outcomes,
+            surface_rotation,
+            horizon_color,
+            chromatic_aberration,
+            bloom,
+            bloom_threshold,
+            bloom_radius,
+            disk_inner,
+            disk_outer,
+            disk_brightness,
+            disk_file,
+            beaming,
+            redshift,
+            chromatic,
+            tonemap,
+            exposure,
+            srgb,
+            eye_separation,
+            interpolate_outcomes,
+            filter_textures,
+            compare,
+            spin,
+            verbose,
+            fov,
+            roll,
+            table_min,
+            table_max,
+            cl,
+            split_devices: _,
+        } = *opts;
+
+        let render_kernel_file = match render_kernel_file {
+            Some(f) => Some(std::path::PathBuf::from(f)),
+            None => cl.kernel_dir.as_ref().map(|dir| dir.join("render.ocl.c")),
+        };
+        let src = match render_kernel_file {
+            Some(ref f) => read_kernel_source(f)?,
+            _ => include_str!("render.ocl.c").to_string(),
+        };
+
+        let filename = match render_kernel_file {
+            Some(ref f) => f.display().to_string(),
+            _ => "the built-in render.ocl.c".to_string(),
+        };
+        let pro_que = cl.pro_que(&filename, &src, (x_res, y_res))?;
+
+        let (dest_buffer, post_buffer) =
+            frame_buffers(&pro_que, x_res, y_res, chromatic_aberration > 0.)?;
+        let bloom_bufs = bloom_buffers(&pro_que, x_res, y_res, bloom > 0.)?;
+
+        // Textures first, since they're quick to load and a bad filename
+        // shouldn't have to wait for the outcome table.
+        let sky = load_image(skybox_file, &pro_que, sky_projection)?;
+        // The two skies don't need to match in size: the kernel samples
+        // each one in normalized coordinates.
+        let sky_b = match skybox_b_file {
+            Some(f) => load_image(f, &pro_que, sky_projection)?,
+            _ => sky.clone(),
+        };
+        let (sphere, plain_sphere) = match surface_file {
+            Some(f) => (
+                load_image(f, &pro_que, SkyProjection::Equirect)?,
+                Some(solid_image(&pro_que, horizon_color)?),
+            ),
+            _ => (solid_image(&pro_que, horizon_color)?, None),
+        };
+        let disk = match disk_file {
+            Some(f) => Some(load_image(f, &pro_que, SkyProjection::Equirect)?),
+            None => None,
+        };
+
+        // The biggest of the table's buffers is the radii, ORBIT_STEPS floats
+        // for each outcome.
+        let radii_bytes = num_outcomes as u64 * ORBIT_STEPS as u64 * 4;
+        if let Some(max) = max_alloc_size(&pro_que) {
+            if radii_bytes > max {
+                return Err(format!(
+                    "{} outcomes take a {}-byte buffer, but the OpenCL device can only allocate {} at a time; at most {} fit",
+                    num_outcomes,
+                    radii_bytes,
+                    max,
+                    max / (ORBIT_STEPS as u64 * 4)
+                ));
+            }
+        }
+        let angle_buf = pro_que.buffer_builder::<f32>().len(num_outcomes).build()?;
+        let angle_result_buf = pro_que.buffer_builder::<u8>().len(num_outcomes).build()?;
+        let radius_buf = pro_que
+            .buffer_builder::<f32>()
+            .len(num_outcomes as usize * ORBIT_STEPS as usize)
+            .build()?;
+        let min_radius_buf = pro_que.buffer_builder::<f32>().len(num_outcomes).build()?;
+        let generated;
+        let table = match table {
+            Some(table) => Some(table),
+            None if spin == 0. => {
+                generated = generate_outcomes(
+                    table_min,
+                    table_max,
+                    num_outcomes,
+                    start_r,
+                    cl,
+                    cache_outcomes,
+                    progress,
+                )?;
+                Some(&generated)
+            }
+            None => None,
+        };
+
+        let (kernel, post_kernel, bloom_kernels) = build_kernels(
+            &pro_que,
+            spin != 0.,
+            post_buffer.is_some(),
+            bloom_bufs.is_some(),
+        )?;
+        let mut schwarz = Schwarz {
+            pro_que,
+            kernel,
+            post_kernel,
+            bloom_kernels,
+            destbuf: dest_buffer,
+            pipeline: None,
+            angles: angle_buf,
+            angle_result: angle_result_buf,
+            radii: radius_buf,
+            min_radii: min_radius_buf,
+            skytex: sky,
+            skytex_b: sky_b,
+            spheretex: sphere,
+            plain_spheretex: plain_sphere,
+            disktex: disk,
+            show_surface: true,
+            num_outcomes,
+            aa,
+            aa_pattern,
+            seed,
+            sky_projection,
+            sky_blend: 0.,
+            sky_brightness,
+            render_kernel_file,
+            ruler_spacing: ruler_spacing.to_radians(),
+            ruler_color: color_prm(ruler_color),
+            show_orbits,
+            photon_sphere_color: color_prm(photon_sphere_color),
+            isco_color: color_prm(isco_color),
+            surface_rotation: surface_rotation.to_radians(),
+            chromatic_aberration,
+            postbuf: post_buffer,
+            bloom,
+            bloom_threshold,
+            bloom_radius,
+            bloombufs: bloom_bufs,
+            disk_inner,
+            disk_outer,
+            disk_brightness,
+            beaming,
+            redshift,
+            chromatic,
+            tonemap,
+            exposure,
+            srgb,
+            eye_separation,
+            interpolate_outcomes,
+            filter_textures,
+            compare,
+            spin,
+            fov: fov.to_radians(),
+            roll: roll.to_radians(),
+            table_min,
+            table_max,
+            cl: cl.clone(),
+            start_r,
+            cache_outcomes,
+            helpers: Vec::new(),
+        };
+        if let Some(table) = table {
+            schwarz.upload_table(table, start_r)?;
+        }
+        schwarz.bind_kernel_args(
+            &schwarz.kernel,
+            schwarz.post_kernel.as_ref(),
+            schwarz.bloom_kernels.as_ref(),
+        )?;
+
+        if verbose {
+            let buffer_bytes = |len: usize, size: usize| (len * size) as u64;
+            let frames = buffer_bytes(schwarz.destbuf.len(), 1)
+                + schwarz
+                    .postbuf
+                    .as_ref()
+                    .map_or(0, |buf| buffer_bytes(buf.len(), 1))
+                + schwarz
+                    .bloombufs
+                    .as_ref()
+                    .map_or(0, |(a, b)| buffer_bytes(a.len() + b.len(), 1));
+            let outcomes = buffer_bytes(schwarz.angles.len(), 4)
+                + buffer_bytes(schwarz.angle_result.len(), 1)
+                + buffer_bytes(schwarz.radii.len(), 4)
+                + buffer_bytes(schwarz.min_radii.len(), 4);
+            // Without a second sky, skytex_b is the same image as skytex.
+            let textures = schwarz.skytex.bytes()
+                + skybox_b_file.map_or(0, |_| schwarz.skytex_b.bytes())
+                + schwarz.spheretex.bytes()
+                + schwarz.plain_spheretex.as_ref().map_or(0, Texture::bytes)
+                + schwarz.disktex.as_ref().map_or(0, Texture::bytes);
+            print_device_usage(
+                &schwarz.pro_que,
+                &[
+                    ("Frame buffers", frames),
+                    ("Outcome table", outcomes),
+                    ("Textures", textures),
+                ],
+            );
+        }
+
+        Ok(schwarz)
+    }
+
+    // The texture to draw on the event horizon: spheretex, unless it's been
+    // toggled off.
+    fn surface_texture(&self) -> &Texture {
+        match self.plain_spheretex {
+            Some(ref plain) if !self.show_surface => plain,
+            _ => &self.spheretex,
+        }
+    }
+
+    /// Switch between the event horizon texture and plain horizon color,
+    /// returning whether the texture is now shown, or None if there's no
+    /// texture to switch to.
+    pub fn toggle_surface(&mut self) -> Result<Option<bool>, String> {
+        if self.plain_spheretex.is_none() {
+            return Ok(None);
+        }
+        self.show_surface = !self.show_surface;
+        self.bind_kernel_args(
+            &self.kernel,
+            self.post_kernel.as_ref(),
+            self.bloom_kernels.as_ref(),
+        )?;
+        for helper in &mut self.helpers {
+            helper.toggle_surface()?;
+        }
+        Ok(Some(self.show_surface))
+    }
+
+    /// Switch to another tone curve, keeping the exposure.
+    pub fn set_tonemap(&mut self, tonemap: ToneMap) -> Result<(), String> {
+        self.tonemap = tonemap;
+        self.bind_kernel_args(
+            &self.kernel,
+            self.post_kernel.as_ref(),
+            self.bloom_kernels.as_ref(),
+        )?;
+        for helper in &mut self.helpers {
+            helper.set_tonemap(tonemap)?;
+        }
+        Ok(())
+    }
+
+    // Set the arguments of the schwarz, chromatic_aberration and bloom
+    // kernels that only change when the buffers or settings do. render sets
+    // the rest.
+    fn bind_kernel_args(
+        &self,
+        kernel: &Kernel,
+        post_kernel: Option<&Kernel>,
+        bloom_kernels: Option<&BloomKernels>,
+    ) -> ocl::Result<()> {
+        kernel.set_arg("buffer", self.postbuf.as_ref().unwrap_or(&self.destbuf))?;
+        kernel.set_arg("angles", &self.angles)?;
+        kernel.set_arg("angle_results", &self.angle_result)?;
+        self.skytex.bind(kernel, "skytex")?;
+        self.skytex_b.bind(kernel, "skytex_b")?;
+        self.surface_texture().bind(kernel, "spheretex")?;
+        kernel.set_arg("aa", self.aa)?;
+        kernel.set_arg("aa_pattern", self.aa_pattern as i32)?;
+        kernel.set_arg("sky_projection", self.sky_projection as i32)?;
+        // The kernel hashes 32 bits; fold the rest in rather than drop them.
+        kernel.set_arg("seed", (self.seed ^ (self.seed >> 32)) as u32)?;
+        kernel.set_arg("num_outcomes", self.num_outcomes)?;
+        kernel.set_arg("sky_brightness", self.sky_brightness)?;
+        kernel.set_arg("ruler_spacing", self.ruler_spacing)?;
+        kernel.set_arg("ruler_color", self.ruler_color)?;
+        kernel.set_arg("photon_ring_color", self.photon_sphere_color)?;
+        kernel.set_arg("isco_ring_color", self.isco_color)?;
+        kernel.set_arg("surface_rotation", self.surface_rotation)?;
+        kernel.set_arg("radii", &self.radii)?;
+        kernel.set_arg("num_radii", ORBIT_STEPS)?;
+        kernel.set_arg("disk_inner", self.disk_inner)?;
+        kernel.set_arg("disk_outer", self.disk_outer)?;
+        kernel.set_arg("disk_brightness", self.disk_brightness)?;
+        // Without a disk texture, the kernel still needs some image there.
+        self.disktex
+            .as_ref()
+            .unwrap_or(&self.spheretex)
+            .bind(kernel, "disktex")?;
+        kernel.set_arg("disk_textured", self.disktex.is_some() as i32)?;
+        kernel.set_arg("beaming", self.beaming as i32)?;
+        kernel.set_arg("min_radii", &self.min_radii)?;
+        kernel.set_arg("redshift", self.redshift)?;
+        kernel.set_arg("chromatic", self.chromatic)?;
+        kernel.set_arg("horizon_r", 2. * GM)?;
+        kernel.set_arg("table_min", self.table_min)?;
+        kernel.set_arg("table_max", self.table_max)?;
+        kernel.set_arg("tonemap", self.tonemap as i32)?;
+        kernel.set_arg("exposure", self.exposure)?;
+        kernel.set_arg("srgb", self.srgb as i32)?;
+        kernel.set_arg("interpolate", self.interpolate_outcomes as i32)?;
+        kernel.set_arg("filter", self.filter_textures as i32)?;
+        kernel.set_arg("compare", self.compare as i32)?;
+        kernel.set_arg(
+            "hdr_textures",
+            self.skytex.is_hdr() as i32
+                | (self.skytex_b.is_hdr() as i32) << 1
+                | (self.surface_texture().is_hdr() as i32) << 2
+                | (self.disktex.as_ref().is_some_and(Texture::is_hdr) as i32) << 3,
+        )?;
+
+        if let (Some(post_kernel), Some(postbuf)) = (post_kernel, self.postbuf.as_ref()) {
+            post_kernel.set_arg("src", postbuf)?;
+            post_kernel.set_arg("dest", &self.destbuf)?;
+            post_kernel.set_arg("amount", self.chromatic_aberration)?;
+        }
+        if let (Some(bloom), Some((across, down))) = (bloom_kernels, self.bloombufs.as_ref()) {
+            bloom.bright_x.set_arg("src", &self.destbuf)?;
+            bloom.bright_x.set_arg("dest", across)?;
+            bloom.bright_x.set_arg("threshold", self.bloom_threshold)?;
+            bloom.bright_x.set_arg("radius", self.bloom_radius as i32)?;
+            bloom.bright_x.set_arg("srgb", self.srgb as i32)?;
+            bloom.blur_y.set_arg("src", across)?;
+            bloom.blur_y.set_arg("dest", down)?;
+            bloom.blur_y.set_arg("radius", self.bloom_radius as i32)?;
+            bloom.add.set_arg("bloom", down)?;
+            bloom.add.set_arg("dest", &self.destbuf)?;
+            bloom.add.set_arg("threshold", self.bloom_threshold)?;
+            bloom.add.set_arg("strength", self.bloom)?;
+            bloom.add.set_arg("srgb", self.srgb as i32)?;
+        }
+        Ok(())
+    }
+
+    /// Move the camera to a new distance from the black hole, in the kernels'
+    /// units. This has to regenerate the outcome table, so it's not quick;
+    /// progress is as for generate_outcomes_gpu, and if it cancels, the camera
+    /// stays where it was.
+    pub fn set_distance(
+        &mut self,
+        start_r: f32,
+        progress: &mut dyn FnMut(f32) -> bool,
+    ) -> Result<(), String> {
+        if self.spin != 0. {
+            // Nothing to regenerate: the kerr kernel traces from start_r
+            // as it goes.
+            self.start_r = start_r;
+            for helper in &mut self.helpers {
+                helper.start_r = start_r;
+            }
+            return Ok(());
+        }
+        let table = generate_outcomes(
+            self.table_min,
+            self.table_max,
+            self.num_outcomes,
+            start_r,
+            &self.cl,
+            self.cache_outcomes,
+            progress,
+        )?;
+        self.upload_table(&table, start_r)?;
+        for helper in &mut self.helpers {
+            helper.upload_table(&table, start_r)?;
+        }
+        Ok(())
+    }
+
+    fn upload_table(&mut self, table: &OutcomeTable, start_r: f32) -> Result<(), String> {
+        self.angles.write(&table.angles).enq()?;
+        self.angle_result.write(&table.outcomes).enq()?;
+        self.radii.write(&table.radii).enq()?;
+        self.min_radii.write(&table.min_radii).enq()?;
+        self.start_r = start_r;
+        Ok(())
+    }
+
+    /// Change the size of the frames we render. Only the frame buffers and
+    /// kernel dimensions depend on it; the outcome table and textures are
+    /// kept, so this is quick.
+    pub fn resize(&mut self, x_res: u32, y_res: u32) -> Result<(), String> {
+        self.pro_que.set_dims((x_res, y_res));
+        self.kernel
+            .set_default_global_work_size((x_res, y_res).into());
+        if let Some(ref mut post_kernel) = self.post_kernel {
+            post_kernel.set_default_global_work_size((x_res, y_res).into());
+        }
+        if let Some(ref mut bloom) = self.bloom_kernels {
+            for kernel in &mut [&mut bloom.bright_x, &mut bloom.blur_y, &mut bloom.add] {
+                kernel.set_default_global_work_size((x_res, y_res).into());
+            }
+        }
+        let (destbuf, postbuf) =
+            frame_buffers(&self.pro_que, x_res, y_res, self.postbuf.is_some())?;
+        self.destbuf = destbuf;
+        self.postbuf = postbuf;
+        self.bloombufs = bloom_buffers(&self.pro_que, x_res, y_res, self.bloombufs.is_some())?;
+        self.pipeline = None;
+        self.bind_kernel_args(
+            &self.kernel,
+            self.post_kernel.as_ref(),
+            self.bloom_kernels.as_ref(),
+        )?;
+        for helper in &mut self.helpers {
+            helper.resize(x_res, y_res)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the frame buffers are big enough for x_res by y_res frames; if
+    /// not, render will refuse them until they've been resized.
+    pub fn fits_frame(&self, x_res: u32, y_res: u32) -> bool {
+        frame_len(x_res, y_res).is_ok_and(|len| len <= self.destbuf.len())
+    }
+
+    /// Render a frame into a new BGRX buffer, with no padding between rows.
+    pub fn render_image(&self, x_res: u32, y_res: u32, look: Look) -> Result<Vec<u8>, String> {
+        let mut data = vec![0u8; frame_len(x_res, y_res)?];
+        self.render(&mut data, x_res, y_res, x_res * 4, PixelFormat::BGRX, look)?;
+        Ok(data)
+    }
+
+    /// Render just the given tile of an x_res by y_res frame into dest, which
+    /// should be where the tile's top left pixel goes; format and pitch are
+    /// as for render. The buffers only need to be big enough for the tile
+    /// (see resize), so this can make frames bigger than the device could
+    /// render in one go. Only the first device is used, and there can't be
+    /// chromatic aberration or bloom, since they need pixels from outside the
+    /// tile.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_tile(
+        &self,
+        dest: &mut [u8],
+        x_res: u32,
+        y_res: u32,
+        tile: Region,
+        pitch: u32,
+        format: PixelFormat,
+        look: Look,
+    ) -> Result<(), String> {
+        if tile.x as u64 + tile.width as u64 > x_res as u64
+            || tile.y as u64 + tile.height as u64 > y_res as u64
+        {
+            return Err(format!(
+                "The tile {:?} doesn't fit in a {}x{} frame",
+                tile, x_res, y_res
+            ));
+        }
+        if self.post_kernel.is_some() || self.bloom_kernels.is_some() {
+            return Err("Chromatic aberration and bloom can't be rendered in tiles".to_string());
+        }
+        let kernel_pitch = self.kernel_pitch(dest, tile.width, tile.height, pitch, format)?;
+        self.enqueue_kernels(
+            self,
+            x_res,
+            y_res,
+            tile,
+            (tile.x, tile.y),
+            kernel_pitch,
+            look,
+            None,
+        )?;
+        read_frame(
+            &self.destbuf,
+            self.pro_que.queue(),
+            None,
+            dest,
+            tile.width,
+            0..tile.height,
+            pitch,
+            format,
+        )
+    }
+
+    /// Like render_image, but a tile_size by tile_size square at a time, with
+    /// render_tile; the buffers need to have been made for tiles that size.
+    pub fn render_image_tiled(
+        &self,
+        x_res: u32,
+        y_res: u32,
+        tile_size: u32,
+        look: Look,
+    ) -> Result<Vec<u8>, String> {
+        let mut data = vec![0u8; frame_len(x_res, y_res)?];
+        let pitch = x_res as usize * 4;
+        for y in (0..y_res).step_by(tile_size as usize) {
+            for x in (0..x_res).step_by(tile_size as usize) {
+                let tile = Region {
+                    x,
+                    y,
+                    width: tile_size.min(x_res - x),
+                    height: tile_size.min(y_res - y),
+                };
+                let start = y as usize * pitch + x as usize * 4;
+                self.render_tile(
+                    &mut data[start..],
+                    x_res,
+                    y_res,
+                    tile,
+                    pitch as u32,
+                    PixelFormat::BGRX,
+                    look,
+                )?;
+            }
+        }
+        Ok(data)
+    }
+
+    // Read the outcome table back from the GPU.
+    fn read_outcomes(&self) -> Result<(Vec<f32>, Vec<u8>), String> {
+        let mut angle_vec = vec![0.0f32; self.angles.len()];
+        let mut outcome_vec = vec![0u8; self.angle_result.len()];
+        self.angles.read(&mut angle_vec).enq()?;
+        self.angle_result.read(&mut outcome_vec).enq()?;
+
+        Ok((angle_vec, outcome_vec))
+    }
+
+    // Read the whole outcome table back from the GPU, for the helpers.
+    fn read_table(&self) -> Result<OutcomeTable, String> {
+        let (angles, outcomes) = self.read_outcomes()?;
+        let mut radii = vec![0.0f32; self.radii.len()];
+        let mut min_radii = vec![0.0f32; self.min_radii.len()];
+        self.radii.read(&mut radii).enq()?;
+        self.min_radii.read(&mut min_radii).enq()?;
+
+        Ok(OutcomeTable {
+            angles,
+            outcomes,
+            radii,
+            min_radii,
+        })
+    }
+
+    /// Rebuild the render kernel from render_kernel_file and, if there's a
+    /// kernel_dir, the outcome table from its rays.ocl.c. If either doesn't
+    /// compile, print the build log and keep the old one.
+    pub fn reload_kernel(&mut self) {
+        if self.render_kernel_file.is_none() {
+            println!("No --render_kernel file or --kernel_dir to reload");
+            return;
+        }
+        self.reload_render_kernel();
+        for helper in &mut self.helpers {
+            helper.reload_render_kernel();
+        }
+
+        // The outcome cache is keyed on the ray kernel source, so this is
+        // quick unless rays.ocl.c has actually changed.
+        if self.cl.kernel_dir.is_some() {
+            match self.set_distance(self.start_r, &mut print_progress) {
+                Ok(()) => println!("Reloaded rays.ocl.c"),
+                Err(err) => println!("Cannot regenerate outcomes, keeping old ones:\n{}", err),
+            }
+        }
+    }
+
+    // The render kernel half of reload_kernel.
+    fn reload_render_kernel(&mut self) {
+        let path = match self.render_kernel_file {
+            Some(ref f) => f.clone(),
+            _ => return,
+        };
+        let filename = path.display();
+        let src = match read_kernel_source(&path) {
+            Ok(src) => src,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+
+        let program = self
+            .cl
+            .program_builder(&src)
+            .devices(self.pro_que.queue().device())
+            .build(self.pro_que.context());
+        // Keep the same context and queue, so the buffers and textures we
+        // already have stay valid.
+        let kernels = program.and_then(|program| {
+            let pro_que = ProQue::new(
+                self.pro_que.context().clone(),
+                self.pro_que.queue().clone(),
+                program,
+                Some(*self.pro_que.dims()),
+            );
+            let (kernel, post_kernel, bloom_kernels) = build_kernels(
+                &pro_que,
+                self.spin != 0.,
+                self.postbuf.is_some(),
+                self.bloombufs.is_some(),
+            )?;
+            self.bind_kernel_args(&kernel, post_kernel.as_ref(), bloom_kernels.as_ref())?;
+            Ok((pro_que, kernel, post_kernel, bloom_kernels))
+        });
+        match kernels {
+            Ok((pro_que, kernel, post_kernel, bloom_kernels)) => {
+                self.pro_que = pro_que;
+                self.kernel = kernel;
+                self.post_kernel = post_kernel;
+                self.bloom_kernels = bloom_kernels;
+                println!("Reloaded {}", filename);
+            }
+            Err(err) => println!("Cannot rebuild {}, keeping old kernel:\n{}", filename, err),
+        }
+    }
+
+    // Queue up the kernels to render the given region of a frame into
+    // destbuf, with the given pitch in pixels. destbuf holds the frame from
+    // origin on: (0, 0) if it has room for the whole thing, or the region's
+    // corner if it only has room for the region. They're left running;
+    // event, if given, gets an event for when they're done. The camera
+    // settings come from view, which is self except for helpers.
+    //
+    // In stereo, the left and right halves of the frame are each a view from
+    // one eye, looking the same way the middle of the view would. Since the
+    // black hole is symmetric, an eye off to the side is just the camera
+    // moved around the hole by the angle the eye is off by, looking that
+    // much away from it, so both eyes can share the outcome table. (This
+    // ignores the eyes being very slightly farther away.)
+    #[allow(clippy::too_many_arguments)]
+    fn enqueue_kernels(
+        &self,
+        view: &Schwarz,
+        x_res: u32,
+        y_res: u32,
+        region: Region,
+        origin: (u32, u32),
+        pitch: u32,
+        look: Look,
+        event: Option<&mut ocl::Event>,
+    ) -> Result<(), String> {
+        self.kernel.set_arg("y_res", y_res)?;
+        self.kernel.set_arg("pitch", pitch)?;
+        self.kernel.set_arg("look_pitch", look.pitch)?;
+        self.kernel.set_arg("sky_blend", view.sky_blend)?;
+        self.kernel.set_arg("screen_scale", (view.fov / 2.).tan())?;
+        self.kernel.set_arg("roll", view.roll)?;
+        // The orbit rings are where spheres of those radii would appear with
+        // no bending, so they're only there if the camera's outside them.
+        let ring = |radius: f32| {
+            if view.show_orbits && view.start_r > radius {
+                radius / (view.start_r * view.start_r - radius * radius).sqrt()
+            } else {
+                0.
+            }
+        };
+        self.kernel.set_arg("photon_ring", ring(3. * GM))?;
+        self.kernel.set_arg("isco_ring", ring(6. * GM))?;
+        if view.spin != 0. {
+            self.kernel.set_arg("spin", view.spin)?;
+            self.kernel.set_arg("start_r", view.start_r)?;
+        }
+        self.kernel.set_arg("buffer_x", origin.0)?;
+        self.kernel.set_arg("buffer_y", origin.1)?;
+
+        // Each view's x offset, width and eye angle (positive to the right).
+        let views = if view.eye_separation > 0. {
+            let angle = (view.eye_separation / 2. / view.start_r).atan();
+            vec![
+                (0, x_res / 2, -angle),
+                (x_res / 2, x_res - x_res / 2, angle),
+            ]
+        } else {
+            vec![(0, x_res, 0.)]
+        };
+        // Each view's part of the region, as a range of x within the view.
+        let views: Vec<_> = views
+            .into_iter()
+            .filter_map(|(x_offset, width, eye_angle)| {
+                let start = region.x.max(x_offset);
+                let end = (region.x + region.width).min(x_offset + width);
+                if start < end {
+                    Some((x_offset, width, eye_angle, start - x_offset..end - x_offset))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        // The queue runs things in order, so the last kernel's event covers
+        // them all.
+        let mut event = event;
+        for (i, &(x_offset, width, eye_angle, ref xs)) in views.iter().enumerate() {
+            let last =
+                i + 1 == views.len() && self.post_kernel.is_none() && self.bloom_kernels.is_none();
+            self.kernel.set_arg("x_res", width)?;
+            self.kernel.set_arg("x_offset", x_offset)?;
+            self.kernel.set_arg("eye_angle", eye_angle)?;
+            // Moving right around the hole is turning left.
+            self.kernel.set_arg("yaw", look.yaw - eye_angle)?;
+            unsafe {
+                // The offset carries through to get_global_id, so each pixel
+                // is drawn as it would be in the whole frame.
+                self.kernel
+                    .cmd()
+                    .global_work_offset((xs.start, region.y))
+                    .global_work_size((xs.end - xs.start, region.height))
+                    .enew(if last { event.take() } else { None })
+                    .enq()?;
+            }
+        }
+
+        if let Some(ref post_kernel) = self.post_kernel {
+            post_kernel.set_arg("x_res", x_res)?;
+            post_kernel.set_arg("y_res", y_res)?;
+            post_kernel.set_arg("pitch", pitch)?;
+            let last = self.bloom_kernels.is_none();
+            unsafe {
+                post_kernel
+                    .cmd()
+                    .enew(if last { event.take() } else { None })
+                    .enq()?;
+            }
+        }
+
+        if let Some(ref bloom) = self.bloom_kernels {
+            for kernel in &[&bloom.bright_x, &bloom.blur_y, &bloom.add] {
+                kernel.set_arg("x_res", x_res)?;
+                kernel.set_arg("y_res", y_res)?;
+                kernel.set_arg("pitch", pitch)?;
+            }
+            unsafe {
+                bloom.bright_x.cmd().enq()?;
+                bloom.blur_y.cmd().enq()?;
+                bloom.add.cmd().enew(event).enq()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Render a BGRX frame into dest, with no padding between rows, and return
+    /// how long the kernels took and how long reading back the result took.
+    /// Like render_pipelined, this only uses the first device.
+    pub fn time_frame(
+        &self,
+        dest: &mut [u8],
+        x_res: u32,
+        y_res: u32,
+        look: Look,
+    ) -> Result<(std::time::Duration, std::time::Duration), String> {
+        // frame_len turns away widths where x_res * 4 would saturate.
+        let pitch = x_res.saturating_mul(4);
+        let kernel_pitch = self.kernel_pitch(dest, x_res, y_res, pitch, PixelFormat::BGRX)?;
+        let start = std::time::Instant::now();
+        self.enqueue_kernels(
+            self,
+            x_res,
+            y_res,
+            Region::whole(x_res, y_res),
+            (0, 0),
+            kernel_pitch,
+            look,
+            None,
+        )?;
+        self.pro_que.finish()?;
+        let rendered = std::time::Instant::now();
+        let len = pitch as usize * y_res as usize;
+        self.destbuf.read(&mut dest[..len]).enq()?;
+        Ok((rendered - start, rendered.elapsed()))
+    }
+
+    // The pitch, in pixels, the kernels should render a frame for dest at,
+    // after checking that neither the frame nor dest is too small for what
+    // the kernels and read_frame will index into them.
+    fn kernel_pitch(
+        &self,
+        dest: &[u8],
+        x_res: u32,
+        y_res: u32,
+        pitch: u32,
+        format: PixelFormat,
+    ) -> Result<u32, String> {
+        if frame_len(x_res, y_res)? > self.destbuf.len() {
+            return Err(format!(
+                "Can't render a {}x{} frame into buffers sized for {} bytes",
+                x_res,
+                y_res,
+                self.destbuf.len()
+            ));
+        }
+        let row_len = x_res as usize * format.bytes_per_pixel;
+        let needed = (pitch as usize)
+            .checked_mul(y_res.saturating_sub(1) as usize)
+            .and_then(|rows| rows.checked_add(row_len));
+        match needed {
+            Some(needed) if pitch as usize >= row_len && dest.len() >= needed => {}
+            _ => {
+                return Err(format!(
+                    "A {}-byte destination with pitch {} is too small for a {}x{} frame",
+                    dest.len(),
+                    pitch,
+                    x_res,
+                    y_res
+                ))
+            }
+        }
+        Ok(if writes_directly(x_res, pitch, format) {
+            pitch / 4
+        } else {
+            x_res
+        })
+    }
+
+    /// Render a frame into dest, which has the given format and pitch (in
+    /// bytes). The kernels always write BGRX; if that's not what format
+    /// wants, the frame is rendered without padding and rearranged on the way
+    /// into dest. With split_devices, each device renders a band of rows, top
+    /// to bottom, and they're all read back into dest.
+    ///
+    /// This is the call for drawing into another program's framebuffer:
+    /// dest can be any slice with room for y_res rows pitch bytes apart (the
+    /// last only needs x_res pixels), such as a Vec or a locked texture. It
+    /// fails rather than writing out of bounds if it's too small, or if
+    /// x_res by y_res is bigger than the frame buffers the renderer has (see
+    /// fits_frame and resize). Rendering leaves the renderer as it was, so
+    /// it can be called again every frame with a new look.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        dest: &mut [u8],
+        x_res: u32,
+        y_res: u32,
+        pitch: u32,
+        format: PixelFormat,
+        look: Look,
+    ) -> Result<(), String> {
+        let kernel_pitch = self.kernel_pitch(dest, x_res, y_res, pitch, format)?;
+
+        // Start every device on its band before waiting on any of them.
+        let devices: Vec<&Schwarz> = std::iter::once(self).chain(&self.helpers).collect();
+        let band = |i: usize| {
+            let row = |i: usize| (y_res as u64 * i as u64 / devices.len() as u64) as u32;
+            row(i)..row(i + 1)
+        };
+        for (i, device) in devices.iter().enumerate() {
+            let rows = band(i);
+            let region = Region {
+                x: 0,
+                y: rows.start,
+                width: x_res,
+                height: rows.end - rows.start,
+            };
+            device.enqueue_kernels(self, x_res, y_res, region, (0, 0), kernel_pitch, look, None)?;
+            device.pro_que.queue().flush()?;
+        }
+        for (i, device) in devices.iter().enumerate() {
+            read_frame(
+                &device.destbuf,
+                device.pro_que.queue(),
+                None,
+                dest,
+                x_res,
+                band(i),
+                pitch,
+                format,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Like render, but without waiting for the frame to finish: this starts
+    /// rendering a frame and fills dest with the *previous* one, which should
+    /// be done by now, so the GPU can be working on the next frame while the
+    /// caller shows this one. The first frame after starting or resizing is
+    /// shown twice, since there's nothing earlier to show. This only uses
+    /// the first device, not split_devices.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_pipelined(
+        &mut self,
+        dest: &mut [u8],
+        x_res: u32,
+        y_res: u32,
+        pitch: u32,
+        format: PixelFormat,
+        look: Look,
+    ) -> Result<(), String> {
+        let mut pipeline = match self.pipeline.take() {
+            Some(pipeline) => pipeline,
+            None => Pipeline {
+                backbuf: self
+                    .pro_que
+                    .buffer_builder()
+                    .len(self.destbuf.len())
+                    .build()?,
+                read_queue: ocl::Queue::new(
+                    self.pro_que.context(),
+                    self.pro_que.queue().device(),
+                    None,
+                )?,
+                pending: None,
+            },
+        };
+
+        let kernel_pitch = self.kernel_pitch(dest, x_res, y_res, pitch, format)?;
+        let shape = (x_res, y_res, kernel_pitch);
+        let mut event = ocl::Event::empty();
+        self.enqueue_kernels(
+            self,
+            x_res,
+            y_res,
+            Region::whole(x_res, y_res),
+            (0, 0),
+            kernel_pitch,
+            look,
+            Some(&mut event),
+        )?;
+        // Get the GPU started now, rather than whenever something next waits
+        // on the queue.
+        self.pro_que.queue().flush()?;
+
+        // The frame just queued is in backbuf after this, and the previous
+        // one in destbuf, where the next frame will go once it's read.
+        std::mem::swap(&mut self.destbuf, &mut pipeline.backbuf);
+        let (buf, wait) = match pipeline.pending.replace((event.clone(), shape)) {
+            Some((previous, previous_shape)) if previous_shape == shape => {
+                (&self.destbuf, previous)
+            }
+            _ => (&pipeline.backbuf, event),
+        };
+        read_frame(
+            buf,
+            &pipeline.read_queue,
+            Some(&wait),
+            dest,
+            x_res,
+            0..y_res,
+            pitch,
+            format,
+        )?;
+
+        self.bind_kernel_args(
+            &self.kernel,
+            self.post_kernel.as_ref(),
+            self.bloom_kernels.as_ref(),
+        )?;
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+}
+
+/// Convert a rendered frame to 8-bit RGBA with no padding between rows. pitch
+/// is in bytes, and format is the layout the pixels are in: BGRX for frames
+/// from Schwarz::render_image, or whatever they were rendered in otherwise.
+pub fn frame_to_rgba(
+    data: &[u8],
+    x_res: u32,
+    y_res: u32,
+    pitch: u32,
+    format: PixelFormat,
+) -> Vec<u8> {
+    let PixelFormat {
+        bytes_per_pixel,
+        offsets: [r, g, b],
+    } = format;
+
+    let mut rgba = Vec::with_capacity(x_res as usize * y_res as usize * 4);
+    for row in data.chunks(pitch as usize).take(y_res as usize) {
+        for p in row[..x_res as usize * bytes_per_pixel].chunks_exact(bytes_per_pixel) {
+            rgba.extend_from_slice(&[p[r], p[g], p[b], 255]);
+        }
+    }
+    rgba
+}
+
+/// Save a rendered frame as an image; pitch and format are as for
+/// frame_to_rgba.
+pub fn save_frame(
+    filename: &str,
+    data: &[u8],
+    x_res: u32,
+    y_res: u32,
+    pitch: u32,
+    format: PixelFormat,
+) -> Result<(), String> {
+    let rgba = frame_to_rgba(data, x_res, y_res, pitch, format);
+    image::save_buffer(filename, &rgba, x_res, y_res, image::RGBA(8))
+        .map_err(|err| format!("Cannot write {}: {}", filename, err))
+}
+
+/// Save the frequency shift the kernels put in the spare fourth byte of each
+/// pixel of a BGRX frame (see encode_shift in render.ocl.c) as a grayscale
+/// image: 128 is no shift, darker is redder, and lighter bluer.
+pub fn save_shift_map(
+    filename: &str,
+    data: &[u8],
+    x_res: u32,
+    y_res: u32,
+    pitch: u32,
+) -> Result<(), String> {
+    let mut gray = Vec::with_capacity(x_res as usize * y_res as usize);
+    for row in data.chunks(pitch as usize).take(y_res as usize) {
+        gray.extend(row[..x_res as usize * 4].chunks_exact(4).map(|p| p[3]));
+    }
+    image::save_buffer(filename, &gray, x_res, y_res, image::Gray(8))
+        .map_err(|err| format!("Cannot write {}: {}", filename, err))
+}
+
+/// Write a screen-space displacement map of the lensing, so other renderers
+/// can fake it as a post-process distortion. For each pixel, this is the
+/// offset from that pixel to the point in the undistorted view that looks in
+/// the same direction, in UV units (fractions of the image width and
+/// height). It's written as a 16-bit RGBA PNG:
+/// - R and G are the u and v offsets, mapped from [-1, 1] onto [0, 65535],
+///   so no offset is 32768;
+/// - B is 65535 where the offset is valid, and 0 where the pixel shows the
+///   event horizon or a direction behind the camera, which no offset of the
+///   undistorted view can reach;
+/// - A is always 65535.
+pub fn export_displacement(
+    filename: &str,
+    schwarz: &Schwarz,
+    x_res: u32,
+    y_res: u32,
+) -> Result<(), String> {
+    // This must match the screen mapping in render.ocl.c.
+    let screen_scale = (schwarz.fov / 2.).tan();
+
+    let (angles, outcomes) = schwarz.read_outcomes()?;
+    let encode = |d: f32| ((d.clamp(-1., 1.) + 1.) / 2. * 65535.).round() as u16;
+
+    let mut data = Vec::with_capacity(x_res as usize * y_res as usize * 8);
+    for y in 0..y_res {
+        for x in 0..x_res {
+            let px = (x as f32 - x_res as f32 / 2.) / (x_res / 2) as f32;
+            let py = (y as f32 - y_res as f32 / 2.) / (x_res / 2) as f32;
+            let len = (px * px + py * py).sqrt();
+
+            // Same lookup as the kernel does.
+            let pos = (len * screen_scale - schwarz.table_min) * angles.len() as f32
+                / (schwarz.table_max - schwarz.table_min);
+            let pos = pos.clamp(0., angles.len() as f32 - 1.001);
+            let posi = if schwarz.interpolate_outcomes {
+                pos as usize
+            } else {
+                (pos + 0.5) as usize
+            };
+            let f = pos - posi as f32;
+            let angle = if !schwarz.interpolate_outcomes || outcomes[posi] != outcomes[posi + 1] {
+                angles[posi]
+            } else {
+                (1. - f) * angles[posi] + f * angles[posi + 1]
+            };
+
+            // Without the hole, a ray at screen radius len would escape at
+            // atan(1 / (screen_scale * len)); invert that to find where the
+            // lensed direction appears in the undistorted view. (Rays bent
+            // past the line of sight come out negative, on the opposite
+            // side of the center.)
+            let pixel =
+                if outcomes[posi] == 1 && len > 0. && angle > 0. && angle < std::f32::consts::PI {
+                    let len0 = 1. / (screen_scale * angle.tan());
+                    let shift = len0 / len - 1.;
+                    let du = px * shift * (x_res / 2) as f32 / x_res as f32;
+                    let dv = py * shift * (x_res / 2) as f32 / y_res as f32;
+                    [encode(du), encode(dv), 65535, 65535]
+                } else {
+                    [32768, 32768, 0, 65535]
+                };
+            for channel in &pixel {
+                data.extend_from_slice(&channel.to_be_bytes());
+            }
+        }
+    }
+
+    image::save_buffer(filename, &data, x_res, y_res, image::RGBA(16))
+        .map_err(|err| format!("Cannot write {}: {}", filename, err))?;
+    println!("Wrote {}", filename);
+    Ok(())
+}
+
+/// Write the outcome table to a CSV file, one row per ray, for comparing
+/// against other integrators. Columns:
+/// - index: the ray's slot in the table;
+/// - tan: the tangent of the ray's angle from the line between the camera
+///   and the black hole, as it leaves the camera (table_min to table_max);
+/// - impact_angle: that angle itself, in radians;
+/// - escaped: 1 if the ray escaped, 0 if it fell into the hole;
+/// - angle: for escaped rays, the direction it escapes in, and for captured
+///   ones, the angle of the point where it crosses the event horizon, both
+///   in radians as gen_outcomes in rays.ocl.c measures them.
+pub fn dump_outcomes(filename: &str, schwarz: &Schwarz) -> Result<(), String> {
+    use std::io::Write;
+
+    let (angles, outcomes) = schwarz.read_outcomes()?;
+    let file = std::fs::File::create(filename)
+        .map_err(|err| format!("Cannot write {}: {}", filename, err))?;
+    let mut out = std::io::BufWriter::new(file);
+    let write_err = |err: std::io::Error| format!("Cannot write {}: {}", filename, err);
+
+    writeln!(out, "index,tan,impact_angle,escaped,angle").map_err(write_err)?;
+    let num = angles.len();
+    for (i, (angle, outcome)) in angles.iter().zip(&outcomes).enumerate() {
+        // The same spacing as gen_outcomes.
+        let frac = i as f32 / (num - 1) as f32;
+        let tan = schwarz.table_max * frac + schwarz.table_min * (1. - frac);
+        writeln!(out, "{},{},{},{},{}", i, tan, tan.atan(), outcome, angle).map_err(write_err)?;
+    }
+    out.flush().map_err(write_err)?;
+    println!("Wrote {}", filename);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests that need an OpenCL device use the default one. They're ignored
+    // unless the gpu_tests feature is on; run them with cargo test --features
+    // gpu_tests, or cargo test -- --ignored.
+    fn test_cl() -> ClConfig {
+        let (platform, device) = select_device(None, None).unwrap();
+        ClConfig {
+            platform,
+            device,
+            options: String::new(),
+            kernel_dir: None,
+        }
+    }
+
+    fn cpu_outcomes(min: f32, max: f32, num: u32, start_r: f32) -> OutcomeTable {
+        generate_outcomes_cpu(min, max, num, start_r).unwrap()
+    }
+
+    fn gpu_outcomes(min: f32, max: f32, num: u32, start_r: f32) -> OutcomeTable {
+        generate_outcomes_gpu(min, max, num, start_r, &test_cl(), &mut |_| true).unwrap()
+    }
+
+    // The tangent of ray slot's angle from the line of sight, spaced as in
+    // gen_outcomes.
+    fn ray_amt(slot: usize, min: f32, max: f32, num: u32) -> f32 {
+        let frac = slot as f32 / (num - 1) as f32;
+        max * frac + min * (1. - frac)
+    }
+
+    // The impact parameter of a ray leaving the camera at start_r as
+    // start_ray has it, ray_amt across for every unit forward: its angular
+    // momentum over its energy.
+    fn impact_parameter(ray_amt: f32, start_r: f32) -> f32 {
+        let q = 1. - 2. * GM / start_r;
+        start_r * ray_amt / (1. + ray_amt * ray_amt * q).sqrt()
+    }
+
+    // Rays with an impact parameter under this, 3 sqrt(3) / 2 times the
+    // Schwarzschild radius, spiral into the photon sphere and are captured.
+    fn critical_impact_parameter() -> f32 {
+        27f32.sqrt() * GM
+    }
+
+    // The physical properties generate_outcomes_gpu and generate_outcomes_cpu
+    // should both have.
+    fn check_photon_sphere(generate: &dyn Fn(f32, f32, u32, f32) -> OutcomeTable) {
+        // Far from the hole, rays barely bend: by close to the weak field
+        // 4 GM / b, plus a bit from the next order (and less a bit, since
+        // they only go so far either side of the camera).
+        let (min, max, num, start_r) = (0.3, 0.5, 3, 2000.);
+        let table = generate(min, max, num, start_r);
+        for i in 0..num as usize {
+            let amt = ray_amt(i, min, max, num);
+            assert_eq!(table.outcomes[i], 1, "ray at tan {}", amt);
+            let deflection = table.angles[i] - 1f32.atan2(amt);
+            let weak = 4. * GM / impact_parameter(amt, start_r);
+            assert!(
+                (deflection - weak).abs() < 0.1 * weak,
+                "ray at tan {} deflects by {}, not {}",
+                amt,
+                deflection,
+                weak
+            );
+        }
+
+        // Anything near the line of sight falls in.
+        let (min, max, num, start_r) = (0., 0.1, 11, 100.);
+        let table = generate(min, max, num, start_r);
+        for i in 0..num as usize {
+            assert_eq!(
+                table.outcomes[i],
+                0,
+                "ray at tan {}",
+                ray_amt(i, min, max, num)
+            );
+        }
+
+        // And going out from there, rays are captured up to the critical
+        // impact parameter and escape after it.
+        let (min, max, num, start_r) = (0., 1., 1001, 100.);
+        let table = generate(min, max, num, start_r);
+        let first_escape = table.outcomes.iter().position(|&o| o == 1).unwrap();
+        assert!(table.outcomes[first_escape..].iter().all(|&o| o == 1));
+        let b = impact_parameter(ray_amt(first_escape, min, max, num), start_r);
+        let critical = critical_impact_parameter();
+        assert!(
+            (b - critical).abs() < 0.005 * critical,
+            "rays escape from b = {}, not {}",
+            b,
+            critical
+        );
+    }
+
+    #[test]
+    fn cpu_outcomes_match_photon_sphere() {
+        check_photon_sphere(&cpu_outcomes);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "gpu_tests"), ignore)]
+    fn gpu_outcomes_match_photon_sphere() {
+        check_photon_sphere(&gpu_outcomes);
+    }
+
+    // Rays just either side of the critical impact parameter wind around
+    // the photon sphere many times, which is where the integrator is most
+    // likely to go wrong.
+    fn check_near_critical(generate: &dyn Fn(f32, f32, u32, f32) -> OutcomeTable) {
+        let start_r = 100.;
+        let critical = critical_impact_parameter();
+        let q = 1. - 2. * GM / start_r;
+        let critical_amt = critical / (start_r * start_r - critical * critical * q).sqrt();
+        let (min, max, num) = (critical_amt - 0.002, critical_amt + 0.002, 201);
+        let table = generate(min, max, num, start_r);
+
+        for i in 0..num as usize {
+            let amt = ray_amt(i, min, max, num);
+            assert!(table.angles[i].is_finite(), "ray at tan {}", amt);
+            assert!(table.min_radii[i].is_finite(), "ray at tan {}", amt);
+            let radii = &table.radii[i * ORBIT_STEPS as usize..(i + 1) * ORBIT_STEPS as usize];
+            assert!(radii.iter().all(|r| r.is_finite()), "ray at tan {}", amt);
+        }
+
+        // Captured rays' angles aren't wrapped, so they say how far round
+        // each one went: further and further, approaching from inside.
+        let first_escape = table.outcomes.iter().position(|&o| o == 1).unwrap();
+        assert!(first_escape > 0);
+        assert!(table.outcomes[first_escape..].iter().all(|&o| o == 1));
+        for i in 1..first_escape {
+            assert!(
+                table.angles[i] > table.angles[i - 1],
+                "ray at tan {} winds less than the one before",
+                ray_amt(i, min, max, num)
+            );
+        }
+        // Escaped ones' angles are wrapped, but the closer they get to the
+        // photon sphere, the longer they stay there; approaching from
+        // outside, they get closer every time.
+        for i in first_escape + 1..num as usize {
+            assert!(
+                table.min_radii[i] > table.min_radii[i - 1],
+                "ray at tan {} gets closer than the one before",
+                ray_amt(i, min, max, num)
+            );
+        }
+        assert!(table.min_radii[first_escape] > 3. * GM * 0.99);
+    }
+
+    #[test]
+    fn cpu_near_critical_rays_are_stable() {
+        check_near_critical(&cpu_outcomes);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "gpu_tests"), ignore)]
+    fn gpu_near_critical_rays_are_stable() {
+        check_near_critical(&gpu_outcomes);
+    }
+
+    #[test]
+    fn isco_moves_with_spin() {
+        for &(spin, isco) in &[(0., 6.), (0.5, 4.233), (0.9, 2.321), (-0.9, 8.717)] {
+            let got = isco_radius(spin);
+            assert!(
+                (got - isco).abs() < 1e-3,
+                "spin {}: {}, not {}",
+                spin,
+                got,
+                isco
+            );
+        }
+    }
+
+    #[test]
+    fn outcome_tables_need_two_rays() {
+        assert!(generate_outcomes_cpu(0., 1., 0, 100.).is_err());
+        assert!(generate_outcomes_cpu(0., 1., 1, 100.).is_err());
+        assert!(generate_outcomes_cpu(0., 1., 2, 100.).is_ok());
+    }
+
+    #[test]
+    fn cpu_outcomes_are_sane() {
+        let (min, max, num, start_r) = (0., 5., 65, 100.);
+        let table = cpu_outcomes(min, max, num, start_r);
+        assert_eq!(table.angles.len(), num as usize);
+        assert_eq!(table.outcomes.len(), num as usize);
+        assert_eq!(table.radii.len(), (num * ORBIT_STEPS) as usize);
+        assert_eq!(table.min_radii.len(), num as usize);
+
+        // Straight at the hole is captured, and at tan 5 it misses easily.
+        assert_eq!(table.outcomes[0], 0);
+        assert_eq!(table.outcomes[num as usize - 1], 1);
+        for i in 0..num as usize {
+            assert!(table.angles[i].is_finite(), "ray {}", i);
+            assert!(table.outcomes[i] <= 1, "ray {}", i);
+            // Every ray starts at the camera, and can't get closer than the
+            // horizon.
+            assert_eq!(table.radii[i * ORBIT_STEPS as usize], start_r, "ray {}", i);
+            assert!(table.min_radii[i] <= start_r, "ray {}", i);
+            assert!(table.min_radii[i] > 2. * GM - 0.01, "ray {}", i);
+        }
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "gpu_tests"), ignore)]
+    fn gpu_and_cpu_outcomes_agree() {
+        let (min, max, num, start_r) = (0., 5., 1024, 100.);
+        let gpu = gpu_outcomes(min, max, num, start_r);
+        let cpu = cpu_outcomes(min, max, num, start_r);
+
+        // The two can round differently, so rays right at the edge of the
+        // photon sphere might go either way (and wind very differently) on
+        // each; only compare the rest.
+        let mut differing = 0;
+        for i in 0..num as usize {
+            if gpu.outcomes[i] != cpu.outcomes[i] {
+                differing += 1;
+                continue;
+            }
+            if cpu.min_radii[i] < 3.5 * GM && cpu.outcomes[i] == 1 {
+                continue;
+            }
+            let (g, c) = (gpu.angles[i], cpu.angles[i]);
+            assert!((g - c).abs() < 5e-3, "ray {}: GPU {}, CPU {}", i, g, c);
+            let (g, c) = (gpu.min_radii[i], cpu.min_radii[i]);
+            assert!((g - c).abs() < 1e-3 * c, "ray {}: GPU {}, CPU {}", i, g, c);
+        }
+        assert!(differing <= 2, "{} rays have different outcomes", differing);
+    }
+}
@@ -1,201 +1,732 @@
+extern crate blackhole_ocl;
 extern crate clap;
-extern crate image;
-extern crate ocl;
 extern crate sdl2;
+extern crate serde;
+extern crate toml;
 
-use ocl::enums::{ImageChannelDataType, ImageChannelOrder, MemObjectType};
-use ocl::{Image, ProQue};
-use sdl2::event::Event;
+use blackhole_ocl::{
+    draw_trace_diagram, dump_outcomes, export_displacement, frame_to_rgba, isco_radius,
+    list_devices, print_progress, save_frame, save_shift_map, select_device, AaPattern, ClConfig,
+    Look, Options, PixelFormat, Schwarz, SkyProjection, ToneMap, GM,
+};
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
-
-// Since Schwarzschild black holes are spherically symmetric, there's really
-// only one dimension that matters for rays, which is the angle between the
-// ray and the line connecting the camera to the center of the black hole.
-// So, precompute a list of "outcomes" for rays over a range of angles; each
-// one tells us whether the ray falls into the black hole (and if so, at what
-// angle around the black hole it does), or whether it escapes (and if so, at
-// what angle). We do this with its own opencl kernel.
-fn generate_outcomes_gpu(min: f32, max: f32, num: u32, start_r: f32) -> (Vec<f32>, Vec<u8>) {
-    let src = include_str!("rays.ocl.c");
-
-    let pro_que = ProQue::builder().src(src).dims(num).build().unwrap();
-    let angle_buffer = pro_que.create_buffer::<f32>().unwrap();
-    let outcome_buffer = pro_que.create_buffer::<u8>().unwrap();
-
-    let kernel = pro_que
-        .kernel_builder("gen_outcomes")
-        .arg(&angle_buffer)
-        .arg(&outcome_buffer)
-        .arg(min)
-        .arg(max)
-        .arg(num)
-        .arg(start_r)
-        .build()
-        .unwrap();
-
-    unsafe {
-        kernel.enq().unwrap();
-    }
-
-    let mut angle_vec = vec![0.0f32; angle_buffer.len()];
-    let mut outcome_vec = vec![0u8; outcome_buffer.len()];
-    angle_buffer.read(&mut angle_vec).enq().unwrap();
-    outcome_buffer.read(&mut outcome_vec).enq().unwrap();
-
-    (angle_vec, outcome_vec)
-}
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
 
-fn build_image(
-    pro_que: &ProQue,
-    data: &[u8],
-    dims: (u32, u32),
-) -> Result<ocl::Image<u8>, ocl::Error> {
-    Image::<u8>::builder()
-        .channel_order(ImageChannelOrder::Rgba)
-        .channel_data_type(ImageChannelDataType::UnormInt8)
-        .image_type(MemObjectType::Image2d)
-        .dims(&dims)
-        .flags(
-            ocl::flags::MEM_READ_ONLY
-                | ocl::flags::MEM_HOST_WRITE_ONLY
-                | ocl::flags::MEM_COPY_HOST_PTR,
-        )
-        .copy_host_slice(data)
-        .queue(pro_que.queue().clone())
-        .build()
+// Widest --fov we allow, in degrees. Much wider than this and the corners of
+// the screen would be past the end of the default outcome table.
+const MAX_FOV: f32 = 150.;
+
+// Highest --antialias we allow without --allow_extreme_aa. Each pixel casts
+// the square of it in rays, so much past this a frame can take long enough
+// for the driver's watchdog to reset the GPU.
+const MAX_AA: u32 = 16;
+
+// The layout of pixels in the given SDL format, for the formats we can
+// render into. (The packed 32-bit formats are named from the most
+// significant byte down, so on a little-endian machine they're backwards in
+// memory.)
+fn pixel_layout(format: PixelFormatEnum) -> Option<PixelFormat> {
+    let (bytes_per_pixel, offsets) = match format {
+        PixelFormatEnum::ARGB8888 | PixelFormatEnum::RGB888 => (4, [2, 1, 0]),
+        PixelFormatEnum::ABGR8888 | PixelFormatEnum::BGR888 => (4, [0, 1, 2]),
+        PixelFormatEnum::BGRA8888 | PixelFormatEnum::BGRX8888 => (4, [1, 2, 3]),
+        PixelFormatEnum::RGBA8888 | PixelFormatEnum::RGBX8888 => (4, [3, 2, 1]),
+        PixelFormatEnum::RGB24 => (3, [0, 1, 2]),
+        PixelFormatEnum::BGR24 => (3, [2, 1, 0]),
+        _ => return None,
+    };
+    Some(PixelFormat {
+        bytes_per_pixel,
+        offsets,
+    })
 }
 
-fn load_image(filename: &str, pro_que: &ProQue) -> Result<ocl::Image<u8>, String> {
-    let img = image::open(filename)
-        .map_err(|err| format!("Cannot open {}: {}", filename, err.to_string()))?
-        .to_rgba();
-    let dims = img.dimensions();
+// The mouse turns the camera a radian around the black hole for every
+// MOUSE_PIXELS_PER_RADIAN it moves, and the camera is level with the equator
+// with the mouse at level_y, which is the middle of the window.
+const MOUSE_PIXELS_PER_RADIAN: f32 = 200.;
+
+// Which way the camera looks with the mouse at (x, y).
+fn mouse_look(x: f32, y: f32, level_y: f32) -> Look {
+    Look {
+        yaw: x / MOUSE_PIXELS_PER_RADIAN,
+        pitch: (y - level_y) / MOUSE_PIXELS_PER_RADIAN,
+    }
+}
 
-    Ok(build_image(pro_que, &img, dims)?)
+// Where the mouse would be for the camera to look a given way; the
+// opposite of mouse_look.
+fn look_mouse(look: Look, level_y: f32) -> (f32, f32) {
+    (
+        look.yaw * MOUSE_PIXELS_PER_RADIAN,
+        look.pitch * MOUSE_PIXELS_PER_RADIAN + level_y,
+    )
 }
 
-fn black_image(pro_que: &ProQue) -> Result<ocl::Image<u8>, String> {
-    Ok(build_image(pro_que, &[0, 0, 0, 0], (1, 1))?)
+// The biggest rectangle the shape of a w by h image that fits in the middle
+// of an area_w by area_h one.
+fn fit_rect((w, h): (u32, u32), (area_w, area_h): (u32, u32)) -> Rect {
+    let scale = (area_w as f32 / w as f32).min(area_h as f32 / h as f32);
+    let width = ((w as f32 * scale) as u32).clamp(1, area_w.max(1));
+    let height = ((h as f32 * scale) as u32).clamp(1, area_h.max(1));
+    Rect::new(
+        (area_w.saturating_sub(width) / 2) as i32,
+        (area_h.saturating_sub(height) / 2) as i32,
+        width,
+        height,
+    )
 }
 
-// Everything we need to keep track of.
-struct Schwarz {
-    // The OpenCL state
-    pro_que: ProQue,
-    // Angle buffer
-    angles: ocl::Buffer<f32>,
-    // Buffer to render into
-    destbuf: ocl::Buffer<u8>,
-    // "result" buffer--whether a ray falls in or escapes
-    angle_result: ocl::Buffer<u8>,
-    // Sky texture
-    skytex: ocl::Image<u8>,
-    // Event horizon texture
-    spheretex: ocl::Image<u8>,
-    // Antialias factor. Applies to each dimension--so the number of rays
-    // per pixel is the *square* of this.
+// Render num_frames frames looking straight at the black hole, and print
+// statistics on how long the kernels and reading back the results took.
+fn benchmark(
+    schwarz: &Schwarz,
+    num_frames: u32,
+    x_res: u32,
+    y_res: u32,
     aa: u32,
-    // Length of the angles and angle_result buffers
-    num_outcomes: u32,
+) -> Result<(), String> {
+    let look = mouse_look(x_res as f32 / 2., y_res as f32 / 2., y_res as f32 / 2.);
+    let mut data = vec![0u8; x_res as usize * y_res as usize * 4];
+    let mut kernel_times = Vec::with_capacity(num_frames as usize);
+    let mut read_times = Vec::with_capacity(num_frames as usize);
+
+    for _ in 0..num_frames {
+        let (kernel_time, read_time) = schwarz.time_frame(&mut data, x_res, y_res, look)?;
+        kernel_times.push(kernel_time);
+        read_times.push(read_time);
+    }
+
+    let total_times: Vec<std::time::Duration> = kernel_times
+        .iter()
+        .zip(&read_times)
+        .map(|(k, r)| *k + *r)
+        .collect();
+    let total: std::time::Duration = total_times.iter().sum();
+    println!(
+        "{} frames at {}x{}, antialias {}:",
+        num_frames, x_res, y_res, aa
+    );
+    for (name, times) in &mut [
+        ("kernels", kernel_times),
+        ("readback", read_times),
+        ("total", total_times),
+    ] {
+        times.sort();
+        let ms = |i: usize| times[i].as_secs_f64() * 1000.;
+        let mean =
+            times.iter().sum::<std::time::Duration>().as_secs_f64() * 1000. / times.len() as f64;
+        println!(
+            "  {:8}  min {:.3}ms  max {:.3}ms  mean {:.3}ms  median {:.3}ms  p95 {:.3}ms",
+            name,
+            ms(0),
+            ms(times.len() - 1),
+            mean,
+            ms(times.len() / 2),
+            ms((times.len() - 1) * 95 / 100),
+        );
+    }
+    println!(
+        "  {:.1} fps average",
+        num_frames as f64 / total.as_secs_f64()
+    );
+    Ok(())
+}
+
+// Draw the camera info overlay (toggled with I) in the top left corner of
+// surface, with a drop shadow so it can be read over any sky.
+fn draw_overlay(
+    font: &sdl2::ttf::Font,
+    surface: &mut sdl2::surface::SurfaceRef,
+    text: &str,
+) -> Result<(), String> {
+    for &(offset, color) in &[(1, Color::RGB(0, 0, 0)), (0, Color::RGB(255, 255, 255))] {
+        let rendered = font
+            .render(text)
+            .blended_wrapped(color, surface.width())
+            .map_err(|e| e.to_string())?;
+        let dest = Rect::new(8 + offset, 8 + offset, rendered.width(), rendered.height());
+        rendered.blit(None, surface, dest)?;
+    }
+    Ok(())
+}
+
+// Render a rows by cols contact sheet of x_res by y_res frames for --grid:
+// the camera's distance steps from the first of distances to the last
+// across the columns, and its fov (in degrees) from the first of fovs to the
+// last down the rows. Each cell is rendered by render, with its settings
+// written in the corner if there's a font, and the sheet comes back as a
+// BGRX buffer cols * x_res wide.
+#[allow(clippy::too_many_arguments)]
+fn render_grid(
+    schwarz: &mut Schwarz,
+    (rows, cols): (u32, u32),
+    (x_res, y_res): (u32, u32),
+    distances: (f32, f32),
+    fovs: (f32, f32),
+    to_kernel_units: f32,
+    font: Option<&sdl2::ttf::Font>,
+    render: &dyn Fn(&Schwarz) -> Result<Vec<u8>, String>,
+) -> Result<Vec<u8>, String> {
+    let step = |(first, last): (f32, f32), i: u32, n: u32| {
+        if n > 1 {
+            first + (last - first) * i as f32 / (n - 1) as f32
+        } else {
+            first
+        }
+    };
+    let pitch = x_res as usize * 4 * cols as usize;
+    let mut sheet = vec![0u8; pitch * y_res as usize * rows as usize];
+
+    // Going down the columns means only regenerating the outcome table once
+    // for each distance.
+    let mut current_distance = distances.0;
+    for col in 0..cols {
+        let distance = step(distances, col, cols);
+        if distance != current_distance {
+            schwarz.set_distance(distance * to_kernel_units, &mut print_progress)?;
+            current_distance = distance;
+        }
+        for row in 0..rows {
+            let fov = step(fovs, row, rows);
+            schwarz.fov = fov.to_radians();
+            println!("Rendering cell {} of {}", col * rows + row + 1, rows * cols);
+            let text = format!("distance {:.2}\nfov {:.1}\u{b0}", distance, fov);
+            place_cell(
+                &mut sheet,
+                cols,
+                (row, col),
+                (x_res, y_res),
+                render(schwarz)?,
+                &text,
+                font,
+            )?;
+        }
+    }
+    Ok(sheet)
+}
+
+// Copy an x_res by y_res BGRX frame into the given row and column of a
+// contact sheet cols frames wide, with label written in its corner if
+// there's a font.
+fn place_cell(
+    sheet: &mut [u8],
+    cols: u32,
+    (row, col): (u32, u32),
+    (x_res, y_res): (u32, u32),
+    mut frame: Vec<u8>,
+    label: &str,
+    font: Option<&sdl2::ttf::Font>,
+) -> Result<(), String> {
+    if let Some(font) = font {
+        let mut surface = sdl2::surface::Surface::from_data(
+            &mut frame,
+            x_res,
+            y_res,
+            x_res * 4,
+            PixelFormatEnum::RGB888,
+        )?;
+        draw_overlay(font, &mut surface, label)?;
+    }
+
+    let row_len = x_res as usize * 4;
+    let pitch = row_len * cols as usize;
+    let cell_start = row as usize * y_res as usize * pitch + col as usize * row_len;
+    for (y, src) in frame.chunks(row_len).enumerate() {
+        let start = cell_start + y * pitch;
+        sheet[start..start + row_len].copy_from_slice(src);
+    }
+    Ok(())
+}
+
+// Render the frame from distance and again from distance + offset (both
+// in --rs units) for --diff, and return how much each channel of each pixel
+// changes between them, as a BGRX buffer. schwarz is left at the second
+// distance.
+fn render_diff(
+    schwarz: &mut Schwarz,
+    distance: f32,
+    offset: f32,
+    to_kernel_units: f32,
+    render: &dyn Fn(&Schwarz) -> Result<Vec<u8>, String>,
+) -> Result<Vec<u8>, String> {
+    println!("Rendering from distance {}", distance);
+    let first = render(schwarz)?;
+    println!("Rendering from distance {}", distance + offset);
+    schwarz.set_distance((distance + offset) * to_kernel_units, &mut print_progress)?;
+    let second = render(schwarz)?;
+    Ok(first
+        .iter()
+        .zip(second)
+        .map(|(&a, b)| a.max(b) - a.min(b))
+        .collect())
+}
+
+// Every tone curve, and what --tonemap calls it.
+const TONEMAPS: [(ToneMap, &str); 3] = [
+    (ToneMap::None, "none"),
+    (ToneMap::Reinhard, "reinhard"),
+    (ToneMap::Aces, "aces"),
+];
+
+// Render the same x_res by y_res frame with each of TONEMAPS for
+// --tonemap_compare, side by side in that order and labeled if there's a
+// font, as a BGRX buffer three frames wide. schwarz is left on the tone
+// curve it started with.
+fn render_tonemap_compare(
+    schwarz: &mut Schwarz,
+    (x_res, y_res): (u32, u32),
+    tonemap: ToneMap,
+    font: Option<&sdl2::ttf::Font>,
+    render: &dyn Fn(&Schwarz) -> Result<Vec<u8>, String>,
+) -> Result<Vec<u8>, String> {
+    let cols = TONEMAPS.len() as u32;
+    let mut sheet = vec![0u8; x_res as usize * 4 * cols as usize * y_res as usize];
+    for (col, &(curve, name)) in TONEMAPS.iter().enumerate() {
+        println!("Rendering with --tonemap {}", name);
+        schwarz.set_tonemap(curve)?;
+        let frame = render(schwarz)?;
+        place_cell(
+            &mut sheet,
+            cols,
+            (0, col as u32),
+            (x_res, y_res),
+            frame,
+            &format!("tonemap {}", name),
+            font,
+        )?;
+    }
+    schwarz.set_tonemap(tonemap)?;
+    Ok(sheet)
+}
+
+// Take the events waiting in event_pump while something slow is going on,
+// keeping them in deferred for the main loop to handle afterwards, and say
+// whether one of them was a request to quit.
+fn poll_quit(event_pump: &mut sdl2::EventPump, deferred: &mut Vec<Event>) -> bool {
+    let mut quit = false;
+    for event in event_pump.poll_iter() {
+        quit |= matches!(event, Event::Quit { .. });
+        deferred.push(event);
+    }
+    quit
+}
+
+// Parse a color given in hex as RRGGBB or RRGGBBAA.
+fn parse_color(s: &str) -> Result<[u8; 4], String> {
+    let bad_color = || format!("Bad color {}: expected RRGGBB or RRGGBBAA in hex", s);
+    if !s.is_ascii() || (s.len() != 6 && s.len() != 8) {
+        return Err(bad_color());
+    }
+
+    let mut color = [255; 4];
+    for (i, c) in color.iter_mut().enumerate().take(s.len() / 2) {
+        *c = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).map_err(|_| bad_color())?;
+    }
+    Ok(color)
+}
+
+// Input recordings, for --record_input and --replay_input, have one event per
+// line, each prefixed with the frame it happened on: "<frame> quit",
+// "<frame> key <keycode>", "<frame> motion <x> <y>" or "<frame> wheel <y>".
+// Only the kinds of event the main loop acts on are recorded.
+fn format_event(event: &Event) -> Option<String> {
+    match *event {
+        Event::Quit { .. } => Some("quit".to_string()),
+        Event::KeyDown {
+            keycode: Some(keycode),
+            ..
+        } => Some(format!("key {}", keycode as i32)),
+        Event::MouseMotion { x, y, .. } => Some(format!("motion {} {}", x, y)),
+        Event::MouseWheel { y, .. } => Some(format!("wheel {}", y)),
+        _ => None,
+    }
+}
+
+fn parse_event(line: &str) -> Option<(u32, Event)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let frame = words.first()?.parse::<u32>().ok()?;
+    let event = match words[1..] {
+        ["quit"] => Event::Quit { timestamp: 0 },
+        ["key", keycode] => Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(Keycode::from_i32(keycode.parse().ok()?)?),
+            scancode: None,
+            keymod: sdl2::keyboard::Mod::empty(),
+            repeat: false,
+        },
+        ["motion", x, y] => Event::MouseMotion {
+            timestamp: 0,
+            window_id: 0,
+            which: 0,
+            mousestate: sdl2::mouse::MouseState::from_sdl_state(0),
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+            xrel: 0,
+            yrel: 0,
+        },
+        ["wheel", y] => Event::MouseWheel {
+            timestamp: 0,
+            window_id: 0,
+            which: 0,
+            x: 0,
+            y: y.parse().ok()?,
+            direction: sdl2::mouse::MouseWheelDirection::Normal,
+        },
+        _ => return None,
+    };
+    Some((frame, event))
+}
+
+fn read_recording(filename: &str) -> Result<Vec<(u32, Event)>, String> {
+    let contents = std::fs::read_to_string(filename)
+        .map_err(|err| format!("Cannot read {}: {}", filename, err))?;
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            parse_event(line).ok_or_else(|| format!("{}:{}: bad event {:?}", filename, i + 1, line))
+        })
+        .collect()
 }
 
-impl Schwarz {
-    fn new(
-        aa: u32,
-        num_outcomes: u32,
-        x_res: u32,
-        y_res: u32,
-        skybox_file: &str,
-        surface_file: Option<&str>,
-    ) -> Result<Schwarz, String> {
-        let src = include_str!("render.ocl.c");
-
-        // TODO: dimensions should be configurable.
-        let pro_que = ProQue::builder()
-            .src(src)
-            .dims((x_res, y_res))
-            .build()
-            .unwrap();
-
-        let dest_buffer = pro_que
-            .buffer_builder()
-            .len(x_res * y_res * 4)
-            .build()
-            .unwrap();
-
-        println!("Generating...");
-        let angle_buf = pro_que
-            .buffer_builder::<f32>()
-            .len(num_outcomes)
-            .build()
-            .unwrap();
-        let angle_result_buf = pro_que
-            .buffer_builder::<u8>()
-            .len(num_outcomes)
-            .build()
-            .unwrap();
-        let (angles, outcomes) = generate_outcomes_gpu(0., 5., num_outcomes, 100.);
-        angle_buf.write(&angles).enq().unwrap();
-        angle_result_buf.write(&outcomes).enq().unwrap();
-        println!("Done");
-
-        println!("Loading textures...");
-        let sky = load_image(skybox_file, &pro_que)?;
-        let sphere = match surface_file {
-            Some(f) => load_image(f, &pro_que)?,
-            _ => black_image(&pro_que)?,
+// Settings that can come from a --config file as well as the command line,
+// named after their flags. Flags on the command line take precedence.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    width: Option<u32>,
+    height: Option<u32>,
+    antialias: Option<u32>,
+    sky_file: Option<String>,
+    surface_file: Option<String>,
+    distance: Option<f32>,
+    fov: Option<f32>,
+    outcomes: Option<u32>,
+}
+
+fn read_config(filename: &str) -> Result<Config, String> {
+    let contents = std::fs::read_to_string(filename)
+        .map_err(|err| format!("Cannot read {}: {}", filename, err))?;
+    toml::from_str(&contents).map_err(|err| format!("{}: {}", filename, err))
+}
+
+// --stream's frame format: a 16-byte header of little-endian u32s (width,
+// height, pixel format, and the number of bytes that follow), then the
+// pixels, row by row with no padding. The only pixel format so far is 0,
+// for 8-bit RGBA.
+const STREAM_RGBA: u32 = 0;
+
+// Listen for --stream viewers on addr, and return a channel to send frames
+// (width, height and RGBA pixels) to them on. Each viewer gets every frame
+// sent after it connects, starting with the latest one; frames sent while
+// the last one's still going out are dropped, so a slow network doesn't
+// hold up rendering. A viewer that hangs up is forgotten.
+fn start_stream(addr: &str) -> Result<std::sync::mpsc::SyncSender<(u32, u32, Vec<u8>)>, String> {
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let listener = std::net::TcpListener::bind(addr)
+        .map_err(|err| format!("Cannot listen on {}: {}", addr, err))?;
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    println!(
+        "Streaming frames on {}",
+        listener.local_addr().map_err(|e| e.to_string())?
+    );
+
+    let (sender, receiver) = std::sync::mpsc::sync_channel::<(u32, u32, Vec<u8>)>(1);
+    std::thread::spawn(move || {
+        let send = |viewer: &mut std::net::TcpStream,
+                    &(width, height, ref rgba): &(u32, u32, Vec<u8>)| {
+            let mut header = Vec::with_capacity(16);
+            for n in &[width, height, STREAM_RGBA, rgba.len() as u32] {
+                header.extend_from_slice(&n.to_le_bytes());
+            }
+            viewer
+                .write_all(&header)
+                .and_then(|_| viewer.write_all(rgba))
+                .is_ok()
         };
-        println!("Done");
-
-        Ok(Schwarz {
-            pro_que,
-            destbuf: dest_buffer,
-            angles: angle_buf,
-            angle_result: angle_result_buf,
-            skytex: sky,
-            spheretex: sphere,
-            num_outcomes,
-            aa,
+        let mut viewers: Vec<std::net::TcpStream> = Vec::new();
+        let mut latest = None;
+        loop {
+            while let Ok((mut viewer, peer)) = listener.accept() {
+                println!("Streaming to {}", peer);
+                // Frames go out whole, however long that takes.
+                if viewer.set_nonblocking(false).is_err() {
+                    continue;
+                }
+                if latest.as_ref().is_none_or(|frame| send(&mut viewer, frame)) {
+                    viewers.push(viewer);
+                }
+            }
+            match receiver.recv_timeout(std::time::Duration::from_millis(100)) {
+                Ok(frame) => {
+                    viewers.retain_mut(|viewer| send(viewer, &frame));
+                    latest = Some(frame);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+    Ok(sender)
+}
+
+// The camera as --save_state leaves it and --load_state picks it back up:
+// which way it's looking, in degrees, its distance, in --rs units, and its
+// field of view.
+#[derive(Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct CameraState {
+    yaw: f32,
+    pitch: f32,
+    roll: f32,
+    distance: f32,
+    fov: f32,
+}
+
+impl CameraState {
+    fn look(&self) -> Look {
+        Look {
+            yaw: self.yaw.to_radians(),
+            pitch: self.pitch.to_radians(),
+        }
+    }
+
+    // Whether this is somewhere the camera can go, around a black hole with
+    // Schwarzschild radius rs.
+    fn check(&self, rs: f32) -> Result<(), String> {
+        if self.distance <= 1.5 * rs {
+            Err(format!(
+                "distance {} is inside the photon sphere",
+                self.distance
+            ))
+        } else if !(self.fov > 0. && self.fov <= MAX_FOV) {
+            Err(format!("fov {} is out of range", self.fov))
+        } else if !(self.yaw.is_finite() && self.pitch.is_finite() && self.roll.is_finite()) {
+            Err("the direction isn't a number".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    // The camera t of the way from self to other, moving each setting
+    // steadily. Angles aren't wrapped, so a yaw of 0 to 360 is a full turn.
+    fn lerp(&self, other: &CameraState, t: f32) -> CameraState {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        CameraState {
+            yaw: mix(self.yaw, other.yaw),
+            pitch: mix(self.pitch, other.pitch),
+            roll: mix(self.roll, other.roll),
+            distance: mix(self.distance, other.distance),
+            fov: mix(self.fov, other.fov),
+        }
+    }
+}
+
+// Read a --load_state file for a black hole with Schwarzschild radius rs.
+// A missing or broken one just means starting from the defaults, so this
+// only warns about it.
+fn read_state(filename: &str, rs: f32) -> Option<CameraState> {
+    let state = std::fs::read_to_string(filename)
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            toml::from_str::<CameraState>(&contents).map_err(|err| err.to_string())
         })
+        .and_then(|state| state.check(rs).map(|()| state));
+    match state {
+        Ok(state) => Some(state),
+        Err(err) => {
+            println!("Ignoring camera state in {}: {}", filename, err);
+            None
+        }
     }
+}
+
+fn write_state(filename: &str, state: &CameraState) -> Result<(), String> {
+    let contents = toml::to_string(state).map_err(|e| e.to_string())?;
+    std::fs::write(filename, contents).map_err(|err| format!("Cannot write {}: {}", filename, err))
+}
+
+// Write the --sidecar for a saved frame: a .json file next to it, with the
+// same name, saying which frame it is (counting from 1, as in the file
+// name), its time in seconds, and the camera it was rendered from, in the
+// same units as --save_state. The settings are all checked numbers, so
+// there's nothing to escape.
+fn write_sidecar(
+    frame_path: &std::path::Path,
+    frame: u32,
+    time: f32,
+    camera: &CameraState,
+) -> Result<(), String> {
+    let contents = format!(
+        "{{\n  \"frame\": {},\n  \"time\": {},\n  \"camera\": {{\n    \"yaw\": {},\n    \"pitch\": {},\n    \"roll\": {},\n    \"distance\": {},\n    \"fov\": {}\n  }}\n}}\n",
+        frame, time, camera.yaw, camera.pitch, camera.roll, camera.distance, camera.fov
+    );
+    let filename = frame_path.with_extension("json");
+    std::fs::write(&filename, contents)
+        .map_err(|err| format!("Cannot write {}: {}", filename.display(), err))
+}
 
-    pub fn render(&self, dest: &mut [u8], x_res: u32, y_res: u32, pitch: u32, cx: f32, cy: f32) {
-        let kernel = self
-            .pro_que
-            .kernel_builder("schwarz")
-            .arg(&self.destbuf)
-            .arg(&self.angles)
-            .arg(&self.angle_result)
-            .arg(x_res)
-            .arg(y_res)
-            .arg(pitch)
-            .arg(cx)
-            .arg(cy)
-            .arg(&self.skytex)
-            .arg(&self.spheretex)
-            .arg(self.aa)
-            .arg(self.num_outcomes)
-            .build()
-            .unwrap();
-
-        unsafe {
-            kernel.enq().unwrap();
-        }
-
-        // Read into the SDL buffer.
-        // I expect this isn't the "proper" way to do this (it looks like surface
-        // access isn't the preferred way to use SDL in rust in general), but it
-        // works well enough...
-        self.destbuf.read(dest).enq().unwrap();
+// An --animate file: a list of [[keyframe]] tables, each one a CameraState
+// plus the time it's reached at, in seconds or whatever units the rest use.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraPath {
+    keyframe: Vec<Keyframe>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Keyframe {
+    time: f32,
+    yaw: f32,
+    pitch: f32,
+    roll: f32,
+    distance: f32,
+    fov: f32,
+}
+
+impl Keyframe {
+    fn camera(&self) -> CameraState {
+        CameraState {
+            yaw: self.yaw,
+            pitch: self.pitch,
+            roll: self.roll,
+            distance: self.distance,
+            fov: self.fov,
+        }
     }
 }
 
+// Read an --animate file for a black hole with Schwarzschild radius rs.
+// Unlike --load_state, there's no sensible default to fall back on, so
+// problems are errors.
+fn read_camera_path(filename: &str, rs: f32) -> Result<Vec<Keyframe>, String> {
+    let contents = std::fs::read_to_string(filename)
+        .map_err(|err| format!("Cannot read {}: {}", filename, err))?;
+    let path = toml::from_str::<CameraPath>(&contents)
+        .map_err(|err| format!("Cannot parse {}: {}", filename, err))?;
+    if path.keyframe.is_empty() {
+        return Err(format!("{} has no keyframes", filename));
+    }
+    for (i, keyframe) in path.keyframe.iter().enumerate() {
+        keyframe
+            .camera()
+            .check(rs)
+            .map_err(|err| format!("Bad keyframe {} in {}: {}", i + 1, filename, err))?;
+        if !keyframe.time.is_finite() {
+            return Err(format!(
+                "Keyframe {} in {} has a time that isn't a number",
+                i + 1,
+                filename
+            ));
+        }
+        if i > 0 && keyframe.time <= path.keyframe[i - 1].time {
+            return Err(format!(
+                "Keyframe {} in {} isn't later than the one before it",
+                i + 1,
+                filename
+            ));
+        }
+    }
+    Ok(path.keyframe)
+}
+
+// Where the camera is at time on a path, holding still before its first
+// keyframe and after its last.
+fn camera_at(keyframes: &[Keyframe], time: f32) -> CameraState {
+    let next = keyframes.iter().position(|k| k.time > time);
+    match next {
+        Some(0) => keyframes[0].camera(),
+        Some(i) => {
+            let (a, b) = (&keyframes[i - 1], &keyframes[i]);
+            a.camera()
+                .lerp(&b.camera(), (time - a.time) / (b.time - a.time))
+        }
+        None => keyframes[keyframes.len() - 1].camera(),
+    }
+}
+
+// Something to render a whole frame with the camera looking a given way.
+type RenderFrame<'a> = &'a dyn Fn(&Schwarz, Look) -> Result<Vec<u8>, String>;
+
+// Render frame_count frames along an --animate path into numbered PNGs in
+// dir, spread evenly in time from its first keyframe to its last, or over
+// only time_scale of the way for slow motion (past the end, the camera
+// holds still at the last keyframe). The
+// camera starts at distance, in --rs units; moving it regenerates the
+// outcome table (or loads it from the cache), so paths that change the
+// distance render much more slowly than ones that don't. With sidecar, each
+// frame gets a .json file describing it too.
+#[allow(clippy::too_many_arguments)]
+fn animate(
+    schwarz: &mut Schwarz,
+    keyframes: &[Keyframe],
+    frame_count: u32,
+    dir: &std::path::Path,
+    sidecar: bool,
+    time_scale: f32,
+    (x_res, y_res): (u32, u32),
+    distance: f32,
+    to_kernel_units: f32,
+    render: RenderFrame,
+) -> Result<(), String> {
+    let start = keyframes[0].time;
+    let end = keyframes[keyframes.len() - 1].time;
+    let mut distance = distance;
+    for frame in 0..frame_count {
+        let t = if frame_count > 1 {
+            frame as f32 / (frame_count - 1) as f32
+        } else {
+            0.
+        };
+        let time = start + (end - start) * t * time_scale;
+        let camera = camera_at(keyframes, time);
+        if camera.distance != distance {
+            schwarz.set_distance(camera.distance * to_kernel_units, &mut print_progress)?;
+            distance = camera.distance;
+        }
+        schwarz.fov = camera.fov.to_radians();
+        schwarz.roll = camera.roll.to_radians();
+
+        let data = render(schwarz, camera.look())?;
+        let path = dir.join(format!("frame_{:05}.png", frame + 1));
+        let filename = path.to_string_lossy();
+        save_frame(&filename, &data, x_res, y_res, x_res * 4, PixelFormat::BGRX)?;
+        if sidecar {
+            write_sidecar(&path, frame + 1, time, &camera)?;
+        }
+        println!("Wrote {} ({}/{})", filename, frame + 1, frame_count);
+    }
+    Ok(())
+}
+
 fn parse_args<'a>() -> clap::ArgMatches<'a> {
     use clap::{App, Arg};
 
     App::new("blackhole_ocl")
         .about("Visualizes Schwarzschild black holes")
+        .arg(Arg::with_name("config")
+             .value_name("filename")
+             .long("config")
+             .help("Read --width, --height, --antialias, --sky_file, --surface_file, --distance, --fov and --outcomes from this TOML file, as keys with the same names. Flags given here override it")
+             .takes_value(true))
+        .arg(Arg::with_name("load_state")
+             .value_name("filename")
+             .long("load_state")
+             .help("Start with the camera direction, distance, fov and roll saved by --save_state, unless flags say otherwise")
+             .takes_value(true))
+        .arg(Arg::with_name("save_state")
+             .value_name("filename")
+             .long("save_state")
+             .help("Save the camera direction, distance, fov and roll to this TOML file when the window closes")
+             .takes_value(true))
         .arg(Arg::with_name("width")
              .value_name("width")
              .long("width")
@@ -210,125 +741,1666 @@ fn parse_args<'a>() -> clap::ArgMatches<'a> {
              .value_name("antialias")
              .visible_alias("aa")
              .long("antialias")
-             .help("Antialiasing factor. Number of rays per pixel will be the square of this number.")
+             .help("Antialiasing factor. Number of rays per pixel will be the square of this number, so it's limited to 16 (defaults to 4)")
+             .takes_value(true))
+        .arg(Arg::with_name("allow_extreme_aa")
+             .long("allow_extreme_aa")
+             .help("Allow --antialias past 16, which can hang the GPU for seconds per frame"))
+        .arg(Arg::with_name("aa_pattern")
+             .value_name("pattern")
+             .long("aa_pattern")
+             .help("Where antialiasing rays go in each pixel: a regular grid, the grid rotated so no two share a row or column, or jittered Halton points (defaults to grid)")
+             .possible_values(&["grid", "rotated", "jittered"])
+             .takes_value(true))
+        .arg(Arg::with_name("seed")
+             .value_name("N")
+             .long("seed")
+             .help("Seed for --aa_pattern jittered; the same seed always gives the same render (defaults to 0)")
+             .takes_value(true))
+        .arg(Arg::with_name("distance")
+             .value_name("r")
+             .long("distance")
+             .help("Distance from the camera to the black hole, in --rs units. Must be more than 1.5 Schwarzschild radii, preferably by a good margin (defaults to 5)")
+             .takes_value(true))
+        .arg(Arg::with_name("rs")
+             .value_name("length")
+             .long("rs")
+             .help("Schwarzschild radius of the black hole. --distance, --disk_inner, --disk_outer and --eye_separation are all in the same units as this, so by default they're in Schwarzschild radii (defaults to 1)")
+             .takes_value(true))
+        .arg(Arg::with_name("mass")
+             .value_name("m")
+             .long("mass")
+             .help("Mass of the black hole, with G = c = 1: the same as --rs twice this")
+             .takes_value(true)
+             .conflicts_with("rs"))
+        .arg(Arg::with_name("fov")
+             .value_name("degrees")
+             .long("fov")
+             .help("Horizontal field of view, at most 150; + and - zoom in and out (defaults to about 143)")
+             .takes_value(true))
+        .arg(Arg::with_name("roll")
+             .value_name("degrees")
+             .long("roll")
+             .help("Tilt the camera clockwise about its view axis; [ and ] adjust it (defaults to 0)")
+             .takes_value(true))
+        .arg(Arg::with_name("stereo")
+             .long("stereo")
+             .help("Render side-by-side views for the left and right eyes, for VR viewers"))
+        .arg(Arg::with_name("verbose")
+             .long("verbose")
+             .help("Print the OpenCL device's memory limits, and how much memory the frame buffers, outcome table and textures take on it"))
+        .arg(Arg::with_name("spin")
+             .value_name("a")
+             .long("spin")
+             .help("Spin the black hole, as a fraction of the most it could spin, from -1 to 1 (defaults to 0). A spinning hole has every ray traced on its own, which is many times slower, and can't be drawn with --stereo, --compare, --redshift, --beaming, --chromatic or --show_orbits")
+             .takes_value(true)
+             .conflicts_with_all(&["dump_outcomes", "export_displacement"]))
+        .arg(Arg::with_name("compare")
+             .long("compare")
+             .help("Show the sky as it would look without the black hole on the left half of the view, split by a line from the lensed view on the right")
+             .conflicts_with("stereo"))
+        .arg(Arg::with_name("eye_separation")
+             .value_name("r")
+             .long("eye_separation")
+             .help("Distance between the eyes for --stereo, in --rs units (defaults to 0.2)")
+             .takes_value(true)
+             .requires("stereo"))
+        .arg(Arg::with_name("angle_min")
+             .value_name("tan")
+             .long("angle_min")
+             .help("Innermost ray to compute outcomes for, as the tangent of its angle from the line to the black hole (defaults to 0)")
+             .takes_value(true))
+        .arg(Arg::with_name("angle_max")
+             .value_name("tan")
+             .long("angle_max")
+             .help("Outermost ray to compute outcomes for, likewise. This has to reach the corners of the screen, which are at tan(fov / 2) times their distance from the center over half the width: about 3.75 for the default --fov and a 4:3 window (defaults to 5)")
+             .takes_value(true))
+        .arg(Arg::with_name("outcomes")
+             .value_name("N")
+             .long("outcomes")
+             .help("Number of ray outcomes to precompute. More gives less banding near the photon ring, but takes longer to generate and more GPU memory (256 bytes each, in one buffer, so the device's largest allocation limits it). At least 2 (defaults to 8192)")
+             .takes_value(true))
+        .arg(Arg::with_name("nearest_outcomes")
+             .long("nearest_outcomes")
+             .help("Use the nearest precomputed outcome for each ray instead of interpolating between the two either side, to see the banding that interpolation hides"))
+        .arg(Arg::with_name("filter")
+             .value_name("mode")
+             .long("filter")
+             .help("How to sample textures: linear blends the texels around each lookup, nearest takes the closest one (defaults to linear)")
+             .possible_values(&["nearest", "linear"])
              .takes_value(true))
         .arg(Arg::with_name("sky_file")
              .value_name("filename")
              .long("sky_file")
              .help("Filename for the skybox")
              .takes_value(true)
-             .required(true))
+             .required_unless_one(&["trace_diagram", "list_devices", "config"]))
+        .arg(Arg::with_name("sky_projection")
+             .value_name("projection")
+             .long("sky_projection")
+             .help("How the sky textures are laid out: an equirectangular map, or a cubemap as a 4:3 horizontal cross or a 6:1 strip of faces (front, right, back, left, up, down) (defaults to equirect)")
+             .possible_values(&["equirect", "cubemap"])
+             .takes_value(true))
+        .arg(Arg::with_name("sky_file_b")
+             .value_name("filename")
+             .long("sky_file_b")
+             .help("Filename for a second skybox to cross-fade with the first (use , and . to blend)")
+             .takes_value(true))
         .arg(Arg::with_name("surface_file")
              .value_name("filename")
              .long("surface_file")
-             .help("Filename for the event horizon texture (defaults to solid black)")
+             .help("Filename for the event horizon texture, an equirectangular map with north at the top (defaults to solid --horizon_color)")
+             .takes_value(true))
+        .arg(Arg::with_name("sky_brightness")
+             .value_name("factor")
+             .long("sky_brightness")
+             .help("Multiplier for the brightness of the skybox (defaults to 1)")
+             .takes_value(true))
+        .arg(Arg::with_name("ruler")
+             .long("ruler")
+             .help("Overlay rings marking angles from the center of the view"))
+        .arg(Arg::with_name("ruler_spacing")
+             .value_name("degrees")
+             .long("ruler_spacing")
+             .help("Angle between the ruler's rings (defaults to 10)")
+             .takes_value(true))
+        .arg(Arg::with_name("ruler_color")
+             .value_name("RRGGBBAA")
+             .long("ruler_color")
+             .help("Color of the ruler, as hex RRGGBB or RRGGBBAA (defaults to ffffff80)")
+             .takes_value(true))
+        .arg(Arg::with_name("show_orbits")
+             .long("show_orbits")
+             .help("Overlay rings where the photon sphere (1.5 rs) and innermost stable circular orbit (3 rs) would appear if light went straight"))
+        .arg(Arg::with_name("photon_sphere_color")
+             .value_name("RRGGBBAA")
+             .long("photon_sphere_color")
+             .help("Color of the --show_orbits photon sphere ring, as hex RRGGBB or RRGGBBAA (defaults to ffa000)")
+             .takes_value(true))
+        .arg(Arg::with_name("isco_color")
+             .value_name("RRGGBBAA")
+             .long("isco_color")
+             .help("Color of the --show_orbits ISCO ring, as hex RRGGBB or RRGGBBAA (defaults to 40c0ff)")
+             .takes_value(true))
+        .arg(Arg::with_name("no_cache")
+             .long("no_cache")
+             .help("Always generate the outcome table, rather than reusing one cached on disk by an earlier run with the same settings"))
+        .arg(Arg::with_name("render_kernel")
+             .value_name("filename")
+             .long("render_kernel")
+             .help("Load the render kernel from this file instead of the built-in copy; press R to reload it")
+             .takes_value(true))
+        .arg(Arg::with_name("kernel_dir")
+             .value_name("dir")
+             .long("kernel_dir")
+             .help("Load rays.ocl.c and render.ocl.c from this directory instead of the built-in copies; press R to reload them (--render_kernel still takes precedence for the render kernel)")
+             .takes_value(true))
+        .arg(Arg::with_name("output")
+             .value_name("filename")
+             .long("output")
+             .help("Render a single frame to this file instead of opening a window")
+             .takes_value(true))
+        .arg(Arg::with_name("shift_map")
+             .value_name("filename")
+             .long("shift_map")
+             .help("Also save the frequency shift of the light reaching each pixel of the --output frame, as a grayscale image: 128 for none (the sky), 0 for the horizon, and above or below for the disk's light coming out bluer or redder")
+             .takes_value(true)
+             .requires("output")
+             .conflicts_with("grid"))
+        .arg(Arg::with_name("tile_size")
+             .value_name("pixels")
+             .long("tile_size")
+             .help("Render the --output frame in squares this size, for frames too big for the OpenCL device to render at once")
+             .takes_value(true)
+             .requires("output")
+             .conflicts_with_all(&["chromatic_aberration", "bloom", "devices"]))
+        .arg(Arg::with_name("grid")
+             .value_name("RxC")
+             .long("grid")
+             .help("Render the --output frame as a contact sheet of R rows by C columns of views, each --width by --height, stepping the distance across from --distance to --grid_distance and the fov down from --fov to --grid_fov")
+             .takes_value(true)
+             .requires("output"))
+        .arg(Arg::with_name("grid_distance")
+             .value_name("r")
+             .long("grid_distance")
+             .help("Distance for the last column of the --grid (defaults to --distance)")
+             .takes_value(true)
+             .requires("grid"))
+        .arg(Arg::with_name("grid_fov")
+             .value_name("degrees")
+             .long("grid_fov")
+             .help("Field of view for the last row of the --grid (defaults to --fov)")
+             .takes_value(true)
+             .requires("grid"))
+        .arg(Arg::with_name("cx")
+             .value_name("x")
+             .long("cx")
+             .help("Horizontal camera position for --output, as a mouse position in pixels (defaults to the center)")
+             .takes_value(true))
+        .arg(Arg::with_name("cy")
+             .value_name("y")
+             .long("cy")
+             .help("Vertical camera position for --output, as a mouse position in pixels (defaults to the center)")
+             .takes_value(true))
+        .arg(Arg::with_name("yaw")
+             .value_name("degrees")
+             .long("yaw")
+             .help("Camera direction around the black hole's poles for --output, instead of --cx")
+             .takes_value(true)
+             .conflicts_with("cx"))
+        .arg(Arg::with_name("pitch")
+             .value_name("degrees")
+             .long("pitch")
+             .help("Camera direction above the black hole's equator for --output, instead of --cy (0 is level with it)")
+             .takes_value(true)
+             .conflicts_with("cy"))
+        .arg(Arg::with_name("export_displacement")
+             .value_name("filename")
+             .long("export_displacement")
+             .help("Write a 16-bit PNG displacement map of the lensing instead of opening a window")
+             .takes_value(true))
+        .arg(Arg::with_name("dump_outcomes")
+             .value_name("filename")
+             .long("dump_outcomes")
+             .help("Write the outcome table to a CSV file (index, tan, impact_angle, escaped, angle; see dump_outcomes in lib.rs) instead of opening a window")
+             .takes_value(true))
+        .arg(Arg::with_name("benchmark")
+             .value_name("N")
+             .long("benchmark")
+             .help("Render N frames looking straight at the black hole, print timing statistics, and exit without opening a window")
+             .takes_value(true))
+        .arg(Arg::with_name("trace_diagram")
+             .value_name("filename")
+             .long("trace_diagram")
+             .help("Draw a diagram of rays bending around the black hole to a PNG, instead of opening a window")
+             .takes_value(true))
+        .arg(Arg::with_name("horizon_color")
+             .value_name("RRGGBB")
+             .long("horizon_color")
+             .help("Color of the event horizon when there's no surface_file, as hex RRGGBB (defaults to black)")
+             .takes_value(true))
+        .arg(Arg::with_name("chromatic_aberration")
+             .value_name("amount")
+             .long("chromatic_aberration")
+             .help("Fringe colors toward the edges of the frame, like a cheap lens. Purely for looks; at most 0.1 (defaults to 0)")
+             .takes_value(true))
+        .arg(Arg::with_name("bloom")
+             .value_name("strength")
+             .long("bloom")
+             .help("Make the brightest parts of the frame, like the photon ring and the disk, glow into their surroundings (defaults to 0, for none; 1 adds the glow at full strength)")
+             .takes_value(true))
+        .arg(Arg::with_name("bloom_threshold")
+             .value_name("brightness")
+             .long("bloom_threshold")
+             .help("Brightness, from 0 up to but not including 1, above which --bloom makes light glow (defaults to 0.8)")
+             .takes_value(true)
+             .requires("bloom"))
+        .arg(Arg::with_name("bloom_radius")
+             .value_name("pixels")
+             .long("bloom_radius")
+             .help("How far --bloom's glow spreads (defaults to 8)")
+             .takes_value(true)
+             .requires("bloom"))
+        .arg(Arg::with_name("disk")
+             .long("disk")
+             .help("Draw a glowing accretion disk around the black hole's equator"))
+        .arg(Arg::with_name("disk_inner")
+             .value_name("r")
+             .long("disk_inner")
+             .help("Inner radius of the accretion disk, in --rs units (defaults to the innermost stable circular orbit: 3 with no --spin, less with a positive one and more with a negative one)")
+             .takes_value(true))
+        .arg(Arg::with_name("disk_outer")
+             .value_name("r")
+             .long("disk_outer")
+             .help("Outer radius of the accretion disk, in --rs units (defaults to 10)")
+             .takes_value(true))
+        .arg(Arg::with_name("disk_file")
+             .value_name("filename")
+             .long("disk_file")
+             .help("Texture for the accretion disk, in place of its glowing temperature ramp. Across the image is outward from --disk_inner to --disk_outer, and down it is once around the disk, anticlockwise seen from above the north pole")
+             .takes_value(true)
+             .requires("disk"))
+        .arg(Arg::with_name("disk_brightness")
+             .value_name("factor")
+             .long("disk_brightness")
+             .help("Multiplier for the brightness of the accretion disk (defaults to 1)")
+             .takes_value(true))
+        .arg(Arg::with_name("beaming")
+             .long("beaming")
+             .help("Doppler shift the accretion disk's light by its orbit, so the side coming toward the camera is brighter and hotter")
+             .requires("disk"))
+        .arg(Arg::with_name("redshift")
+             .long("redshift")
+             .help("Tint the sky red where rays pass close to the black hole, to show how deep they went. (Not physical: light from infinity arrives with the same shift whatever path it took.) With --disk, this also shifts the disk's light by its climb out of the hole's gravity, which is physical"))
+        .arg(Arg::with_name("redshift_strength")
+             .value_name("factor")
+             .long("redshift_strength")
+             .help("How strong the --redshift tint is (defaults to 1)")
+             .takes_value(true))
+        .arg(Arg::with_name("chromatic")
+             .value_name("strength")
+             .long("chromatic")
+             .help("Bend red light a little more than green and blue a little less, for a prism-like fringe around the photon ring. Purely for looks; at most 0.5 (defaults to 0)")
+             .takes_value(true))
+        .arg(Arg::with_name("tonemap")
+             .value_name("mode")
+             .long("tonemap")
+             .help("Tone curve for the sky, so bright skies roll off instead of clipping to white (defaults to none)")
+             .possible_values(&["none", "reinhard", "aces"])
+             .takes_value(true))
+        .arg(Arg::with_name("diff")
+             .value_name("r")
+             .long("diff")
+             .help("Render the --output frame from --distance and again from this much farther out (negative for closer), in --rs units, and save how much each pixel changes between them instead: black where nothing moves, brightest where the lensed sky shifts most")
+             .takes_value(true)
+             .allow_hyphen_values(true)
+             .requires("output")
+             .conflicts_with_all(&["grid", "tonemap_compare"]))
+        .arg(Arg::with_name("tonemap_compare")
+             .long("tonemap_compare")
+             .help("Render the --output frame once with each --tonemap, side by side in a row (labeled if there's a --font), to pick one for the scene")
+             .requires("output")
+             .conflicts_with_all(&["grid", "tonemap"]))
+        .arg(Arg::with_name("exposure")
+             .value_name("factor")
+             .long("exposure")
+             .help("Multiplier for the sky's brightness before --tonemap, in linear light (defaults to 1)")
+             .takes_value(true)
+             .requires("tonemap"))
+        .arg(Arg::with_name("linear")
+             .long("linear")
+             .help("Blend and antialias the textures' values as they are, rather than decoding them from sRGB first. This is how it used to look; it comes out darker where the sky is squeezed"))
+        .arg(Arg::with_name("surface_rotation")
+             .value_name("degrees")
+             .long("surface_rotation")
+             .help("Rotate the event horizon texture around the poles by this much")
+             .takes_value(true))
+        .arg(Arg::with_name("platform")
+             .value_name("N")
+             .long("platform")
+             .help("Index of the OpenCL platform to use (see --list_devices)")
+             .takes_value(true))
+        .arg(Arg::with_name("device")
+             .value_name("N")
+             .long("device")
+             .help("Index of the OpenCL device to use on that platform (see --list_devices)")
+             .takes_value(true))
+        .arg(Arg::with_name("devices")
+             .value_name("N,N,...")
+             .long("devices")
+             .help("Indices of several OpenCL devices on that platform to split each frame between, in bands from top to bottom; mostly worthwhile for large --output stills")
+             .takes_value(true)
+             .conflicts_with_all(&["device", "pipeline", "chromatic_aberration", "bloom"]))
+        .arg(Arg::with_name("list_devices")
+             .long("list_devices")
+             .help("List OpenCL platforms and devices, and exit"))
+        .arg(Arg::with_name("fast_math")
+             .long("fast_math")
+             .help("Compile the kernels with -cl-fast-relaxed-math: faster, but less precise"))
+        .arg(Arg::with_name("cl_options")
+             .value_name("options")
+             .long("cl_options")
+             .help("Extra options to pass to the OpenCL compiler")
              .takes_value(true))
         .arg(Arg::with_name("fps")
              .long("fps")
              .help("Periodically print frame rate")
         )
+        .arg(Arg::with_name("throttle")
+             .value_name("fraction")
+             .long("throttle")
+             .help("Fraction of each frame to spend sleeping, to keep the GPU cooler (defaults to 0)")
+             .takes_value(true))
+        .arg(Arg::with_name("smoothing")
+             .value_name("fraction")
+             .long("smoothing")
+             .help("How far the view moves toward the mouse each frame: 1 follows it exactly, smaller values glide more (defaults to 0.25)")
+             .takes_value(true))
+        .arg(Arg::with_name("sensitivity")
+             .value_name("factor")
+             .long("sensitivity")
+             .help("How far the view turns for a given mouse movement (defaults to 1)")
+             .takes_value(true))
+        .arg(Arg::with_name("invert_x")
+             .long("invert_x")
+             .help("Turn the view the other way for sideways mouse movement"))
+        .arg(Arg::with_name("invert_y")
+             .long("invert_y")
+             .help("Turn the view the other way for up and down mouse movement, like a flight sim"))
+        .arg(Arg::with_name("font")
+             .value_name("filename")
+             .long("font")
+             .help("TrueType font for the camera info overlay, which I toggles, and for labeling --grid cells")
+             .takes_value(true))
+        .arg(Arg::with_name("input_udp")
+             .value_name("port")
+             .long("input_udp")
+             .help("Listen on this UDP port for camera orientation packets (yaw, pitch, roll as three little-endian f32 radians)")
+             .takes_value(true))
+        .arg(Arg::with_name("stream")
+             .value_name("addr:port")
+             .long("stream")
+             .help("Also send each frame to TCP viewers connecting on this address, as a 16-byte header (width, height, format and length, as little-endian u32s) followed by RGBA pixels")
+             .takes_value(true))
+        .arg(Arg::with_name("record_input")
+             .value_name("filename")
+             .long("record_input")
+             .help("Record mouse and keyboard input to this file")
+             .takes_value(true))
+        .arg(Arg::with_name("replay_input")
+             .value_name("filename")
+             .long("replay_input")
+             .help("Replay input recorded with --record_input instead of using the mouse and keyboard")
+             .takes_value(true))
+        .arg(Arg::with_name("max_frames")
+             .value_name("frames")
+             .long("max_frames")
+             .help("Exit after rendering this many frames")
+             .takes_value(true))
+        .arg(Arg::with_name("max_seconds")
+             .value_name("seconds")
+             .long("max_seconds")
+             .help("Exit after running for this many seconds")
+             .takes_value(true))
+        .arg(Arg::with_name("max_fps")
+             .value_name("N")
+             .long("max_fps")
+             .help("Render at most this many frames per second")
+             .takes_value(true))
+        .arg(Arg::with_name("vsync")
+             .long("vsync")
+             .help("Render at most as many frames per second as the display refreshes at"))
+        .arg(Arg::with_name("pipeline")
+             .long("pipeline")
+             .help("Render the next frame while showing the last one: faster, but the view lags a frame behind")
+             .conflicts_with("frames"))
+        .arg(Arg::with_name("frames")
+             .value_name("dir")
+             .long("frames")
+             .help("Save every frame to a numbered PNG in this directory, at --width by --height whatever size the window is (the window shows them scaled to fit)")
+             .takes_value(true))
+        .arg(Arg::with_name("sidecar")
+             .long("sidecar")
+             .help("Write a .json file next to each of the --frames, with its frame number, its time in seconds (along the --animate path, or since starting) and the camera it was rendered from")
+             .requires("frames"))
+        .arg(Arg::with_name("frame_count")
+             .value_name("N")
+             .long("frame_count")
+             .help("Exit after saving this many --frames")
+             .takes_value(true)
+             .requires("frames"))
+        .arg(Arg::with_name("animate")
+             .value_name("filename")
+             .long("animate")
+             .help("Render --frame_count frames along the camera path in this TOML file to --frames, without opening a window. The file is a list of [[keyframe]] tables, each with a time and the same keys as --save_state writes; the camera moves steadily between them")
+             .takes_value(true)
+             .requires_all(&["frames", "frame_count"])
+             .conflicts_with_all(&["output", "grid"]))
+        .arg(Arg::with_name("time_scale")
+             .value_name("scale")
+             .long("time_scale")
+             .help("Play the --animate path at this times its speed: the --frame_count frames only cover this fraction of it, for slow motion below 1 (defaults to 1)")
+             .takes_value(true)
+             .requires("animate"))
+        .arg(Arg::with_name("orbit")
+             .value_name("seconds")
+             .long("orbit")
+             .help("Ignore the mouse and turn the camera all the way around the black hole once every this many seconds")
+             .takes_value(true))
         .get_matches()
 }
 
 fn main() -> Result<(), String> {
     let matches = parse_args();
+    let config = match matches.value_of("config") {
+        Some(filename) => read_config(filename)?,
+        None => Config::default(),
+    };
 
-    let x_res = matches
-        .value_of("width")
-        .unwrap_or("1600")
-        .parse::<u32>()
+    let x_res = match matches.value_of("width") {
+        Some(w) => w.parse::<u32>().map_err(|e| e.to_string())?,
+        None => config.width.unwrap_or(1600),
+    };
+    let y_res = match matches.value_of("height") {
+        Some(h) => h.parse::<u32>().map_err(|e| e.to_string())?,
+        None => config.height.unwrap_or(1200),
+    };
+    // Mouse positions are relative to the window we open at this size, even
+    // if the surface later comes out bigger.
+    let level_y = y_res as f32 / 2.;
+    let mut aa = match matches.value_of("antialias") {
+        Some(a) => a.parse::<u32>().map_err(|e| e.to_string())?,
+        None => config.antialias.unwrap_or(4),
+    };
+    if aa == 0 {
+        return Err("--antialias must be at least 1".to_string());
+    }
+    if aa > MAX_AA && !matches.is_present("allow_extreme_aa") {
+        println!(
+            "Limiting --antialias to {} ({} rays per pixel); pass --allow_extreme_aa to go higher",
+            MAX_AA,
+            MAX_AA * MAX_AA
+        );
+        aa = MAX_AA;
+    }
+    let num_outcomes = match matches.value_of("outcomes") {
+        Some(n) => n.parse::<u32>().map_err(|e| e.to_string())?,
+        None => config.outcomes.unwrap_or(8192),
+    };
+    // The kernels interpolate between neighboring outcomes, so there have to
+    // be at least two.
+    if num_outcomes < 2 {
+        return Err(format!(
+            "--outcomes must be at least 2; got {}",
+            num_outcomes
+        ));
+    }
+    let sky_brightness = matches
+        .value_of("sky_brightness")
+        .unwrap_or("1")
+        .parse::<f32>()
         .map_err(|e| e.to_string())?;
-    let y_res = matches
-        .value_of("height")
-        .unwrap_or("1200")
-        .parse::<u32>()
+    let surface_rotation = matches
+        .value_of("surface_rotation")
+        .unwrap_or("0")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    let mut chromatic_aberration = matches
+        .value_of("chromatic_aberration")
+        .unwrap_or("0")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    if chromatic_aberration > 0.1 {
+        println!("Limiting --chromatic_aberration to 0.1");
+        chromatic_aberration = 0.1;
+    }
+    let spin = matches
+        .value_of("spin")
+        .unwrap_or("0")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    let bloom = matches
+        .value_of("bloom")
+        .unwrap_or("0")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    let bloom_threshold = matches
+        .value_of("bloom_threshold")
+        .unwrap_or("0.8")
+        .parse::<f32>()
         .map_err(|e| e.to_string())?;
-    let aa = matches
-        .value_of("antialias")
-        .unwrap_or("4")
+    let bloom_radius = matches
+        .value_of("bloom_radius")
+        .unwrap_or("8")
         .parse::<u32>()
         .map_err(|e| e.to_string())?;
-    let skybox_filename = matches.value_of("sky_file").unwrap();
-    let surface_filename = matches.value_of("surface_file");
+    let ruler_spacing = if matches.is_present("ruler") {
+        matches
+            .value_of("ruler_spacing")
+            .unwrap_or("10")
+            .parse::<f32>()
+            .map_err(|e| e.to_string())?
+    } else {
+        0.
+    };
+    // Lengths on the command line are in units where the Schwarzschild
+    // radius is rs; the kernels' units have it at 2 * GM.
+    let rs = match (matches.value_of("rs"), matches.value_of("mass")) {
+        (Some(rs), _) => rs.parse::<f32>().map_err(|e| e.to_string())?,
+        (None, Some(m)) => 2. * m.parse::<f32>().map_err(|e| e.to_string())?,
+        (None, None) => 1.,
+    };
+    if rs <= 0. {
+        return Err(format!(
+            "--rs and --mass must be more than 0; got rs = {}",
+            rs
+        ));
+    }
+    let to_kernel_units = 2. * GM / rs;
+    let state = match matches.value_of("load_state") {
+        Some(filename) => read_state(filename, rs),
+        None => None,
+    };
+    let (disk_inner, disk_outer) = if matches.is_present("disk") {
+        // Inside the innermost stable orbit, gas falls straight in rather
+        // than orbiting, so that's where the disk stops unless asked
+        // otherwise. A spinning hole's horizon is inside rs, too.
+        let inner = match matches.value_of("disk_inner") {
+            Some(r) => r.parse::<f32>().map_err(|e| e.to_string())?,
+            None => isco_radius(spin) * rs / 2.,
+        };
+        let outer = matches
+            .value_of("disk_outer")
+            .unwrap_or("10")
+            .parse::<f32>()
+            .map_err(|e| e.to_string())?;
+        let horizon = rs / 2. * (1. + (1. - spin * spin).sqrt());
+        if inner < horizon || outer <= inner {
+            return Err(format!(
+                "The disk has to be outside the event horizon at {}, with --disk_outer past --disk_inner; got {} to {}",
+                horizon, inner, outer
+            ));
+        }
+        (inner * to_kernel_units, outer * to_kernel_units)
+    } else {
+        (0., 0.)
+    };
+    let table_min = matches
+        .value_of("angle_min")
+        .unwrap_or("0")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    let table_max = matches
+        .value_of("angle_max")
+        .unwrap_or("5")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    if table_min < 0. || table_max <= table_min {
+        return Err(format!(
+            "Need 0 <= --angle_min < --angle_max; got {} and {}",
+            table_min, table_max
+        ));
+    }
+    let fov = match matches.value_of("fov") {
+        Some(f) => f.parse::<f32>().map_err(|e| e.to_string())?,
+        // Wide enough that the edges of the screen are rays three times as
+        // far out as they are forward.
+        None => match state {
+            Some(ref state) => state.fov,
+            None => config.fov.unwrap_or_else(|| 2. * 3f32.atan().to_degrees()),
+        },
+    };
+    if fov <= 0. || fov > MAX_FOV {
+        return Err(format!(
+            "--fov must be more than 0 and at most {} degrees; got {}",
+            MAX_FOV, fov
+        ));
+    }
+    let roll = match matches.value_of("roll") {
+        Some(r) => r.parse::<f32>().map_err(|e| e.to_string())?,
+        None => state.as_ref().map_or(0., |state| state.roll),
+    };
+    let redshift = if matches.is_present("redshift") {
+        matches
+            .value_of("redshift_strength")
+            .unwrap_or("1")
+            .parse::<f32>()
+            .map_err(|e| e.to_string())?
+    } else {
+        0.
+    };
+    let mut chromatic = matches
+        .value_of("chromatic")
+        .unwrap_or("0")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    if chromatic > 0.5 {
+        println!("Limiting --chromatic to 0.5");
+        chromatic = 0.5;
+    }
+    let eye_separation = if matches.is_present("stereo") {
+        matches
+            .value_of("eye_separation")
+            .unwrap_or("0.2")
+            .parse::<f32>()
+            .map_err(|e| e.to_string())?
+    } else {
+        0.
+    };
+    if eye_separation < 0. {
+        return Err(format!(
+            "--eye_separation can't be negative; got {}",
+            eye_separation
+        ));
+    }
+    let sky_projection = match matches.value_of("sky_projection") {
+        Some("cubemap") => SkyProjection::Cubemap,
+        _ => SkyProjection::Equirect,
+    };
+    let aa_pattern = match matches.value_of("aa_pattern") {
+        Some("rotated") => AaPattern::Rotated,
+        Some("jittered") => AaPattern::Jittered,
+        _ => AaPattern::Grid,
+    };
+    let seed = matches
+        .value_of("seed")
+        .unwrap_or("0")
+        .parse::<u64>()
+        .map_err(|e| e.to_string())?;
+    let tonemap = match matches.value_of("tonemap") {
+        Some(name) => TONEMAPS
+            .iter()
+            .find(|&&(_, n)| n == name)
+            .map_or(ToneMap::None, |&(curve, _)| curve),
+        None => ToneMap::None,
+    };
+    let exposure = matches
+        .value_of("exposure")
+        .unwrap_or("1")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    let disk_brightness = matches
+        .value_of("disk_brightness")
+        .unwrap_or("1")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    let horizon_color = parse_color(matches.value_of("horizon_color").unwrap_or("000000"))?;
+    let ruler_color = parse_color(matches.value_of("ruler_color").unwrap_or("ffffff80"))?;
+    let photon_sphere_color =
+        parse_color(matches.value_of("photon_sphere_color").unwrap_or("ffa000"))?;
+    let isco_color = parse_color(matches.value_of("isco_color").unwrap_or("40c0ff"))?;
+    if matches.is_present("list_devices") {
+        return list_devices();
+    }
+
+    let platform_index = match matches.value_of("platform") {
+        Some(n) => Some(n.parse::<usize>().map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let device_index = match matches.value_of("device") {
+        Some(n) => Some(n.parse::<usize>().map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let device_indices = match matches.value_of("devices") {
+        Some(list) => list
+            .split(',')
+            .map(|n| {
+                n.trim()
+                    .parse::<usize>()
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => vec![device_index],
+    };
+    let (platform, device) = select_device(platform_index, device_indices[0])?;
+    // The camera has to be outside the photon sphere (1.5 Schwarzschild
+    // radii), or no ray could ever escape; it's best kept well outside it.
+    let distance = match matches.value_of("distance") {
+        Some(r) => r.parse::<f32>().map_err(|e| e.to_string())?,
+        None => match state {
+            Some(ref state) => state.distance,
+            None => config.distance.unwrap_or(5.),
+        },
+    };
+    if distance <= 1.5 * rs {
+        return Err(format!(
+            "--distance must be outside the photon sphere at {}; got {}",
+            1.5 * rs,
+            distance
+        ));
+    }
+    let start_r = distance * to_kernel_units;
+
+    let mut cl_options = matches.value_of("cl_options").unwrap_or("").to_string();
+    if matches.is_present("fast_math") {
+        cl_options.push_str(" -cl-fast-relaxed-math");
+    }
+    let cl = ClConfig {
+        platform,
+        device,
+        options: cl_options,
+        kernel_dir: matches.value_of("kernel_dir").map(std::path::PathBuf::from),
+    };
+    let mut split_devices = Vec::new();
+    for &index in &device_indices[1..] {
+        let (_, device) = select_device(platform_index, index)?;
+        split_devices.push(ClConfig {
+            device,
+            ..cl.clone()
+        });
+    }
+
+    if let Some(filename) = matches.value_of("trace_diagram") {
+        return draw_trace_diagram(filename, start_r, &cl);
+    }
+
+    let throttle = matches
+        .value_of("throttle")
+        .unwrap_or("0")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    if !(0. ..1.).contains(&throttle) {
+        return Err(format!(
+            "--throttle must be at least 0 and less than 1; got {}",
+            throttle
+        ));
+    }
+    let smoothing = matches
+        .value_of("smoothing")
+        .unwrap_or("0.25")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    if smoothing <= 0. || smoothing > 1. {
+        return Err(format!(
+            "--smoothing must be more than 0 and at most 1; got {}",
+            smoothing
+        ));
+    }
+    let sensitivity = matches
+        .value_of("sensitivity")
+        .unwrap_or("1")
+        .parse::<f32>()
+        .map_err(|e| e.to_string())?;
+    if sensitivity <= 0. {
+        return Err(format!(
+            "--sensitivity must be more than 0; got {}",
+            sensitivity
+        ));
+    }
+    let flip = |invert| if matches.is_present(invert) { -1. } else { 1. };
+    let mouse_scale = (
+        sensitivity * flip("invert_x"),
+        sensitivity * flip("invert_y"),
+    );
+    let skybox_filename = matches
+        .value_of("sky_file")
+        .or(config.sky_file.as_deref())
+        .ok_or_else(|| "No --sky_file given, on the command line or in --config".to_string())?;
+    let skybox_b_filename = matches.value_of("sky_file_b");
+    let surface_filename = matches
+        .value_of("surface_file")
+        .or(config.surface_file.as_deref());
+    let render_kernel_filename = matches.value_of("render_kernel");
     let fps = matches.is_present("fps");
+    let pipeline = matches.is_present("pipeline");
+    let max_frames = match matches.value_of("max_frames") {
+        Some(n) => Some(n.parse::<u32>().map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let max_seconds = match matches.value_of("max_seconds") {
+        Some(t) => Some(t.parse::<f32>().map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let sidecar = matches.is_present("sidecar");
+    let frame_dir = match matches.value_of("frames") {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .map_err(|err| format!("Cannot create {}: {}", dir, err))?;
+            Some(std::path::PathBuf::from(dir))
+        }
+        None => None,
+    };
+    let max_frames = match matches.value_of("frame_count") {
+        Some(n) => {
+            let n = n.parse::<u32>().map_err(|e| e.to_string())?;
+            Some(max_frames.map_or(n, |m| m.min(n)))
+        }
+        None => max_frames,
+    };
+    let max_fps = match matches.value_of("max_fps") {
+        Some(n) => Some(n.parse::<f32>().map_err(|e| e.to_string())?),
+        None => None,
+    };
+    if max_fps.is_some_and(|n| n <= 0.) {
+        return Err("--max_fps must be more than 0".to_string());
+    }
+    let orbit = match matches.value_of("orbit") {
+        Some(t) => Some(t.parse::<f32>().map_err(|e| e.to_string())?),
+        None => None,
+    };
+    if orbit.is_some_and(|t| t <= 0.) {
+        return Err("--orbit must be more than 0 seconds".to_string());
+    }
+
+    let grid = match matches.value_of("grid") {
+        Some(g) => {
+            let bad_grid = || format!("Bad --grid {}: expected rows x columns, like 3x4", g);
+            let mut dims = g.splitn(2, 'x').map(|n| n.parse::<u32>());
+            match (dims.next(), dims.next()) {
+                (Some(Ok(rows)), Some(Ok(cols))) if rows > 0 && cols > 0 => Some((rows, cols)),
+                _ => return Err(bad_grid()),
+            }
+        }
+        None => None,
+    };
+    let grid_distance = match matches.value_of("grid_distance") {
+        Some(r) => r.parse::<f32>().map_err(|e| e.to_string())?,
+        None => distance,
+    };
+    if grid_distance <= 1.5 * rs {
+        return Err(format!(
+            "--grid_distance must be outside the photon sphere at {}; got {}",
+            1.5 * rs,
+            grid_distance
+        ));
+    }
+    let grid_fov = match matches.value_of("grid_fov") {
+        Some(f) => f.parse::<f32>().map_err(|e| e.to_string())?,
+        None => fov,
+    };
+    if grid_fov <= 0. || grid_fov > MAX_FOV {
+        return Err(format!(
+            "--grid_fov must be more than 0 and at most {} degrees; got {}",
+            MAX_FOV, grid_fov
+        ));
+    }
+
+    let tile_size = match matches.value_of("tile_size") {
+        Some(n) => {
+            let n = n.parse::<u32>().map_err(|e| e.to_string())?;
+            if n == 0 {
+                return Err("--tile_size must be at least 1".to_string());
+            }
+            Some(n)
+        }
+        None => None,
+    };
+    // When tiling, the buffers only need to hold one tile.
+    let (buffer_x_res, buffer_y_res) = match tile_size {
+        Some(n) => (n.min(x_res), n.min(y_res)),
+        None => (x_res, y_res),
+    };
+    let opts = Options {
+        aa,
+        aa_pattern,
+        seed,
+        num_outcomes,
+        start_r,
+        x_res: buffer_x_res,
+        y_res: buffer_y_res,
+        skybox_file: skybox_filename,
+        skybox_b_file: skybox_b_filename,
+        sky_projection,
+        surface_file: surface_filename,
+        render_kernel_file: render_kernel_filename,
+        sky_brightness,
+        ruler_spacing,
+        ruler_color,
+        show_orbits: matches.is_present("show_orbits"),
+        photon_sphere_color,
+        isco_color,
+        cache_outcomes: !matches.is_present("no_cache"),
+        surface_rotation,
+        horizon_color,
+        chromatic_aberration,
+        bloom,
+        bloom_threshold,
+        bloom_radius,
+        disk_inner,
+        disk_outer,
+        disk_brightness,
+        disk_file: matches.value_of("disk_file"),
+        beaming: matches.is_present("beaming"),
+        redshift,
+        chromatic,
+        tonemap,
+        exposure,
+        srgb: !matches.is_present("linear"),
+        eye_separation: eye_separation * to_kernel_units,
+        interpolate_outcomes: !matches.is_present("nearest_outcomes"),
+        filter_textures: matches.value_of("filter") != Some("nearest"),
+        compare: matches.is_present("compare"),
+        spin,
+        verbose: matches.is_present("verbose"),
+        fov,
+        roll,
+        table_min,
+        table_max,
+        cl: &cl,
+        split_devices: &split_devices,
+    };
+    // Without one of the options that render and exit, there'll be a
+    // window, so start SDL now: then closing it (or Ctrl-C, which SDL turns
+    // into the same thing) during the long wait for the outcome table can
+    // cancel it. Anything else that happens meanwhile is kept in deferred
+    // for the main loop.
+    let windowed = ![
+        "animate",
+        "output",
+        "export_displacement",
+        "dump_outcomes",
+        "benchmark",
+    ]
+    .iter()
+    .any(|&name| matches.is_present(name));
+    let (sdl_context, mut event_pump) = if windowed {
+        let sdl_context = sdl2::init()?;
+        let event_pump = sdl_context.event_pump()?;
+        (Some(sdl_context), Some(event_pump))
+    } else {
+        (None, None)
+    };
+    let mut deferred = Vec::new();
+    let mut quit = false;
+    println!("Loading textures and outcomes...");
+    let schwarz = Schwarz::new(&opts, &mut |done| {
+        print_progress(done);
+        if let Some(ref mut event_pump) = event_pump {
+            quit = poll_quit(event_pump, &mut deferred);
+        }
+        !quit
+    });
+    if quit {
+        return Ok(());
+    }
+    let mut schwarz = schwarz?;
+    println!("Done");
+
+    let ttf_context = match matches.value_of("font") {
+        Some(_) => Some(sdl2::ttf::init().map_err(|e| e.to_string())?),
+        None => None,
+    };
+    let font = match (&ttf_context, matches.value_of("font")) {
+        (Some(ttf_context), Some(filename)) => Some(
+            ttf_context
+                .load_font(filename, 16)
+                .map_err(|err| format!("Cannot load {}: {}", filename, err))?,
+        ),
+        _ => None,
+    };
+
+    let render_at = |schwarz: &Schwarz, look: Look| match tile_size {
+        Some(n) => schwarz.render_image_tiled(x_res, y_res, n, look),
+        None => schwarz.render_image(x_res, y_res, look),
+    };
+
+    if let (Some(filename), Some(ref dir), Some(frame_count)) =
+        (matches.value_of("animate"), &frame_dir, max_frames)
+    {
+        let keyframes = read_camera_path(filename, rs)?;
+        let time_scale = match matches.value_of("time_scale") {
+            Some(s) => s.parse::<f32>().map_err(|e| e.to_string())?,
+            None => 1.,
+        };
+        if !(time_scale > 0. && time_scale.is_finite()) {
+            return Err(format!(
+                "--time_scale {} isn't a positive number",
+                time_scale
+            ));
+        }
+        return animate(
+            &mut schwarz,
+            &keyframes,
+            frame_count,
+            dir,
+            sidecar,
+            time_scale,
+            (x_res, y_res),
+            distance,
+            to_kernel_units,
+            &render_at,
+        );
+    }
+
+    if let Some(filename) = matches.value_of("output") {
+        let cx = match matches.value_of("cx") {
+            Some(x) => x.parse::<f32>().map_err(|e| e.to_string())?,
+            None => x_res as f32 / 2.,
+        };
+        let cy = match matches.value_of("cy") {
+            Some(y) => y.parse::<f32>().map_err(|e| e.to_string())?,
+            None => y_res as f32 / 2.,
+        };
+        let mut look = match state {
+            Some(ref state) if !matches.is_present("cx") && !matches.is_present("cy") => {
+                state.look()
+            }
+            _ => mouse_look(cx, cy, level_y),
+        };
+        if let Some(yaw) = matches.value_of("yaw") {
+            look.yaw = yaw.parse::<f32>().map_err(|e| e.to_string())?.to_radians();
+        }
+        if let Some(pitch) = matches.value_of("pitch") {
+            look.pitch = pitch
+                .parse::<f32>()
+                .map_err(|e| e.to_string())?
+                .to_radians();
+        }
+        let render = |schwarz: &Schwarz| render_at(schwarz, look);
+        let (frame, width, height) = match grid {
+            Some((rows, cols)) => {
+                let (width, height) = match (cols.checked_mul(x_res), rows.checked_mul(y_res)) {
+                    (Some(w), Some(h)) if w.checked_mul(4).is_some() => (w, h),
+                    _ => return Err(format!("A {}x{} --grid is too big", rows, cols)),
+                };
+                let sheet = render_grid(
+                    &mut schwarz,
+                    (rows, cols),
+                    (x_res, y_res),
+                    (distance, grid_distance),
+                    (fov, grid_fov),
+                    to_kernel_units,
+                    font.as_ref(),
+                    &render,
+                )?;
+                (sheet, width, height)
+            }
+            None if matches.is_present("diff") => {
+                let offset = matches
+                    .value_of("diff")
+                    .unwrap()
+                    .parse::<f32>()
+                    .map_err(|e| e.to_string())?;
+                if offset.is_nan() || distance + offset <= 1.5 * rs {
+                    return Err(format!(
+                        "--diff {} would move the camera inside the photon sphere at {}",
+                        offset,
+                        1.5 * rs
+                    ));
+                }
+                let diff = render_diff(&mut schwarz, distance, offset, to_kernel_units, &render)?;
+                (diff, x_res, y_res)
+            }
+            None if matches.is_present("tonemap_compare") => {
+                let width = match (TONEMAPS.len() as u32).checked_mul(x_res) {
+                    Some(w) if w.checked_mul(4).is_some() => w,
+                    _ => return Err("The --tonemap_compare frames are too wide".to_string()),
+                };
+                let sheet = render_tonemap_compare(
+                    &mut schwarz,
+                    (x_res, y_res),
+                    tonemap,
+                    font.as_ref(),
+                    &render,
+                )?;
+                (sheet, width, y_res)
+            }
+            None => (render(&schwarz)?, x_res, y_res),
+        };
+        save_frame(
+            filename,
+            &frame,
+            width,
+            height,
+            width * 4,
+            PixelFormat::BGRX,
+        )?;
+        println!("Wrote {}", filename);
+        if let Some(filename) = matches.value_of("shift_map") {
+            save_shift_map(filename, &frame, width, height, width * 4)?;
+            println!("Wrote {}", filename);
+        }
+        return Ok(());
+    }
+
+    if let Some(filename) = matches.value_of("export_displacement") {
+        return export_displacement(filename, &schwarz, x_res, y_res);
+    }
+
+    if let Some(filename) = matches.value_of("dump_outcomes") {
+        return dump_outcomes(filename, &schwarz);
+    }
 
-    let sdl_context = sdl2::init()?;
+    if let Some(n) = matches.value_of("benchmark") {
+        let n = n.parse::<u32>().map_err(|e| e.to_string())?;
+        if n == 0 {
+            return Err("--benchmark needs at least 1 frame".to_string());
+        }
+        return benchmark(&schwarz, n, x_res, y_res, aa);
+    }
+
+    let sdl_context = match sdl_context {
+        Some(sdl_context) => sdl_context,
+        None => sdl2::init()?,
+    };
     let video_subsystem = sdl_context.video()?;
 
-    let window = video_subsystem
-        .window("Schwarzschild black hole visualizer", x_res, y_res)
-        .position_centered()
-        .build()
-        .map_err(|e| e.to_string())?;
+    let mut window_builder =
+        video_subsystem.window("Schwarzschild black hole visualizer", x_res, y_res);
+    window_builder.position_centered().resizable();
+    let window = window_builder.build().map_err(|e| e.to_string())?;
 
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut event_pump = match event_pump {
+        Some(event_pump) => event_pump,
+        None => sdl_context.event_pump()?,
+    };
 
-    let pixel_format = window.surface(&event_pump)?.pixel_format_enum();
-    if pixel_format != PixelFormatEnum::ARGB8888 && pixel_format != PixelFormatEnum::RGB888 {
-        return Err(format!("Can only handle ARGB8888/RGB888 pixel format right now; got {:?}", pixel_format));
+    // Whether to draw the overlay, and the frame rate to show on it,
+    // smoothed a little so it's readable.
+    let mut show_info = false;
+    let mut info_fps = 0.;
+    let mut last_frame = std::time::Instant::now();
+
+    // We draw straight to the window's surface, which SDL can't sync to the
+    // display, so --vsync just caps the frame rate at the refresh rate.
+    let refresh_rate = if matches.is_present("vsync") {
+        match video_subsystem
+            .current_display_mode(window.display_index()?)?
+            .refresh_rate
+        {
+            0 => Some(60.),
+            rate => Some(rate as f32),
+        }
+    } else {
+        None
+    };
+    let min_frame_time = match (max_fps, refresh_rate) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
     }
+    .map(|fps| std::time::Duration::from_secs_f32(1. / fps));
 
-    // TODO: the number of outcomes could be made configurable.
-    let schwarz = Schwarz::new(aa, 8192, x_res, y_res, skybox_filename, surface_filename)?;
+    let pixel_format = window.surface(&event_pump)?.pixel_format_enum();
+    let layout = pixel_layout(pixel_format).ok_or_else(|| {
+        format!(
+            "Cannot render into the window's {:?} pixel format",
+            pixel_format
+        )
+    })?;
 
     let mut time = std::time::SystemTime::now();
     let mut frames = 0;
+    let start_time = time;
+    let mut total_frames = 0;
 
     // "Effective" mouse position. This is a smoothed version of the physical position,
     // since we don't want small mouse movements to cause a "jump"--it's better to smooth
     // out the motion.
-    let mut mx = x_res as f32 / 2.;
-    let mut my = y_res as f32 / 2.;
+    let center = (x_res as f32 / 2., y_res as f32 / 2.);
+    // The size to render --frames at, even if the window changes.
+    let frame_size = (x_res, y_res);
+    let (start_mx, start_my) = match state {
+        Some(ref state) => look_mouse(state.look(), level_y),
+        None => center,
+    };
+    let mut mx = start_mx;
+    let mut my = start_my;
     // Current physical mouse position.
     let mut cmx = mx;
     let mut cmy = my;
     // Acceleration factor for mouse smoothing. Each frame, the effective position moves
     // that fraction of the way toward the physical position.
-    let acc = 0.25;
+    let acc = smoothing;
+
+    let udp_input = match matches.value_of("input_udp") {
+        Some(port) => {
+            let port = port.parse::<u16>().map_err(|e| e.to_string())?;
+            let socket = std::net::UdpSocket::bind(("0.0.0.0", port))
+                .map_err(|err| format!("Cannot listen on UDP port {}: {}", port, err))?;
+            socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+            Some(socket)
+        }
+        None => None,
+    };
+
+    let stream = match matches.value_of("stream") {
+        Some(addr) => Some(start_stream(addr)?),
+        None => None,
+    };
+
+    let mut recording = match matches.value_of("record_input") {
+        Some(filename) => Some(std::io::BufWriter::new(
+            std::fs::File::create(filename)
+                .map_err(|err| format!("Cannot create {}: {}", filename, err))?,
+        )),
+        None => None,
+    };
+    let replay = match matches.value_of("replay_input") {
+        Some(filename) => Some(read_recording(filename)?),
+        None => None,
+    };
+    let mut next_replay = 0;
+    // Camera distance (in --rs units) asked for with the arrow keys
+    // or mouse wheel, and when it last changed. Regenerating the outcome
+    // table is slow, so it waits until the distance has stopped changing for
+    // a moment.
+    let mut target_distance = distance;
+    // The distance the outcome table is for, which is what's on screen.
+    let mut shown_distance = distance;
+    let mut distance_changed: Option<std::time::SystemTime> = None;
+    // Set when S is pressed, so the next frame gets saved once it's rendered.
+    let mut save_requested = false;
+    // When nothing is happening, we can stop rendering until something does:
+    // redraw is set whenever there's an event, and otherwise we wait for the
+    // view center to stop moving. Replays, --max_frames and the like count
+    // frames, so they keep rendering regardless.
+    let can_idle =
+        replay.is_none() && max_frames.is_none() && frame_dir.is_none() && orbit.is_none();
+    let mut redraw = true;
+    // With --pipeline, what's on screen is a frame behind, so once the view
+    // settles it takes one more frame to catch up before we can idle.
+    let mut pipeline_behind = false;
+    // P stops the view moving and rendering, until it's pressed again.
+    let mut paused = false;
 
     'running: loop {
-        // Update effective mouse position
-        mx = (1. - acc) * mx + acc * cmx;
-        my = (1. - acc) * my + acc * cmy;
-
-        // FPS counters are nice, so why not.
-        frames += 1;
-        if fps && frames == 100 {
-            let duration = time.elapsed().unwrap();
-            println!(
-                "{} frames in {}ms = {} fps",
-                frames,
-                duration.as_millis(),
-                (frames * 1000) as f32 / (duration.as_millis() as f32)
-            );
-            time = std::time::SystemTime::now();
-            frames = 0;
+        let iteration_start = std::time::SystemTime::now();
+        if let Some(ref socket) = udp_input {
+            // Orientation packets are three little-endian f32s: yaw, pitch
+            // and roll, in radians. If several have arrived since last frame,
+            // only the latest one matters.
+            let mut packet = [0u8; 12];
+            while let Ok((len, _)) = socket.recv_from(&mut packet) {
+                if len != packet.len() {
+                    continue;
+                }
+                let yaw = f32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
+                let pitch = f32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+                let (x, y) = look_mouse(Look { yaw, pitch }, level_y);
+                cmx = x;
+                cmy = y;
+                schwarz.roll = f32::from_le_bytes([packet[8], packet[9], packet[10], packet[11]]);
+                redraw = true;
+            }
         }
 
+        if let Some(changed) = distance_changed {
+            if changed.elapsed().unwrap() > std::time::Duration::from_millis(250) {
+                // Closing the window (or Ctrl-C, which SDL turns into the
+                // same thing) while the table's being generated gives up on
+                // it and quits straight away.
+                let mut quit = false;
+                let moved = schwarz.set_distance(target_distance * to_kernel_units, &mut |done| {
+                    print_progress(done);
+                    quit = poll_quit(&mut event_pump, &mut deferred);
+                    !quit
+                });
+                if quit {
+                    break 'running;
+                }
+                moved?;
+                shown_distance = target_distance;
+                distance_changed = None;
+                redraw = true;
+            }
+        }
+
+        if max_frames.is_some_and(|n| total_frames >= n)
+            || max_seconds.is_some_and(|t| start_time.elapsed().unwrap().as_secs_f32() >= t)
         {
-            // New scope because .surface borrows event_pump.
-            let mut surface = window.surface(&event_pump)?;
-            let pitch = surface.pitch() / 4; // We want the pitch in pixels
-            let (x_res, y_res) = surface.size();
-            let pixels = surface.without_lock_mut().unwrap();
+            break 'running;
+        }
+
+        if paused {
+            // Leave the view where it is.
+        } else if let Some(period) = orbit {
+            // One full turn of yaw per period.
+            let t = start_time.elapsed().unwrap().as_secs_f32() / period;
+            mx =
+                x_res as f32 / 2. + 2. * std::f32::consts::PI * MOUSE_PIXELS_PER_RADIAN * t.fract();
+        } else {
+            // Update effective mouse position
+            mx = (1. - acc) * mx + acc * cmx;
+            my = (1. - acc) * my + acc * cmy;
+        }
+
+        let settled = paused
+            || can_idle
+                && !redraw
+                && !save_requested
+                && (mx - cmx).abs() < 0.01
+                && (my - cmy).abs() < 0.01;
+        if settled && !pipeline_behind {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        } else {
+            redraw = false;
+            pipeline_behind = pipeline && !settled;
+
+            // FPS counters are nice, so why not.
+            frames += 1;
+            if fps && frames == 100 {
+                let duration = time.elapsed().unwrap();
+                println!(
+                    "{} frames in {}ms = {} fps",
+                    frames,
+                    duration.as_millis(),
+                    (frames * 1000) as f32 / (duration.as_millis() as f32)
+                );
+                time = std::time::SystemTime::now();
+                frames = 0;
+            }
+            let frame_time = last_frame.elapsed().as_secs_f32();
+            last_frame = std::time::Instant::now();
+            if frame_time > 0. {
+                info_fps = 0.9 * info_fps + 0.1 / frame_time;
+            }
+
+            let frame_start = std::time::SystemTime::now();
+            {
+                // New scope because .surface borrows event_pump.
+                let mut surface = window.surface(&event_pump)?;
+                let pitch = surface.pitch();
+                let (x_res, y_res) = surface.size();
+                let look = mouse_look(mx, my, level_y);
+                if let Some(ref dir) = frame_dir {
+                    // Saved frames are rendered at --width by --height,
+                    // whatever size the window is, and scaled to fit it.
+                    let (frame_x_res, frame_y_res) = frame_size;
+                    let mut frame = render_at(&schwarz, look)?;
+                    let path = dir.join(format!("frame_{:05}.png", total_frames + 1));
+                    let filename = path.to_string_lossy();
+                    save_frame(
+                        &filename,
+                        &frame,
+                        frame_x_res,
+                        frame_y_res,
+                        frame_x_res * 4,
+                        PixelFormat::BGRX,
+                    )?;
+                    if sidecar {
+                        let camera = CameraState {
+                            yaw: look.yaw.to_degrees(),
+                            pitch: look.pitch.to_degrees(),
+                            roll: schwarz.roll.to_degrees(),
+                            distance: shown_distance,
+                            fov: schwarz.fov.to_degrees(),
+                        };
+                        let time = start_time.elapsed().unwrap().as_secs_f32();
+                        write_sidecar(&path, total_frames + 1, time, &camera)?;
+                    }
+                    match max_frames {
+                        Some(n) => println!("Wrote {} ({}/{})", filename, total_frames + 1, n),
+                        None => println!("Wrote {}", filename),
+                    }
 
-            schwarz.render(pixels, x_res, y_res, pitch, mx, my);
-            surface.update_window().unwrap();
+                    let image = sdl2::surface::Surface::from_data(
+                        &mut frame,
+                        frame_x_res,
+                        frame_y_res,
+                        frame_x_res * 4,
+                        PixelFormatEnum::RGB888,
+                    )?;
+                    surface.fill_rect(None, Color::RGB(0, 0, 0))?;
+                    image.blit_scaled(
+                        None,
+                        &mut surface,
+                        fit_rect((frame_x_res, frame_y_res), (x_res, y_res)),
+                    )?;
+                } else {
+                    // The surface can come out bigger than the size we last
+                    // heard about (some compositors pad windows without
+                    // saying so), so make sure the buffers can hold it.
+                    if !schwarz.fits_frame(x_res, y_res) {
+                        println!(
+                            "The window surface is {}x{}; resizing the frame buffers to match",
+                            x_res, y_res
+                        );
+                        schwarz.resize(x_res, y_res)?;
+                    }
+                    let pixels = surface
+                        .without_lock_mut()
+                        .ok_or_else(|| "Cannot write to the window surface".to_string())?;
+
+                    // Saved frames should be the one that was asked for, not
+                    // the one before it.
+                    if pipeline && !save_requested {
+                        schwarz.render_pipelined(pixels, x_res, y_res, pitch, layout, look)?;
+                    } else {
+                        schwarz.render(pixels, x_res, y_res, pitch, layout, look)?;
+                    }
+                }
+                let pixels = surface
+                    .without_lock_mut()
+                    .ok_or_else(|| "Cannot write to the window surface".to_string())?;
+                if let Some(ref stream) = stream {
+                    let rgba = frame_to_rgba(pixels, x_res, y_res, pitch, layout);
+                    // If the last frame's still being sent, skip this one.
+                    let _ = stream.try_send((x_res, y_res, rgba));
+                }
+                if save_requested {
+                    let secs = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs();
+                    let filename = format!("blackhole_{}.png", secs);
+                    match save_frame(&filename, pixels, x_res, y_res, pitch, layout) {
+                        Ok(()) => println!("Saved {}", filename),
+                        Err(err) => println!("{}", err),
+                    }
+                    save_requested = false;
+                }
+                // Saved frames don't get the overlay.
+                if let (true, Some(ref font)) = (show_info, &font) {
+                    let text = format!(
+                        "yaw {:.0}\u{b0}, pitch {:.0}\u{b0}\ndistance {:.2}\nfov {:.1}\u{b0}\nroll {:.0}\u{b0}\n{}x{}\n{:.1} fps",
+                        look.yaw.to_degrees(),
+                        look.pitch.to_degrees(),
+                        target_distance,
+                        schwarz.fov.to_degrees(),
+                        schwarz.roll.to_degrees(),
+                        x_res,
+                        y_res,
+                        info_fps
+                    );
+                    draw_overlay(font, &mut surface, &text)?;
+                }
+                surface.update_window()?;
+            }
+            total_frames += 1;
+
+            if throttle > 0. {
+                // Sleep long enough that rendering only takes up (1 - throttle)
+                // of each frame.
+                let busy = frame_start.elapsed().unwrap();
+                std::thread::sleep(busy.mul_f32(throttle / (1. - throttle)));
+            }
         }
-        for event in event_pump.poll_iter() {
+
+        // Replayed events take the place of live input, except that closing
+        // or resizing the window still works.
+        let mut events: Vec<Event> = deferred.drain(..).chain(event_pump.poll_iter()).collect();
+        if !events.is_empty() {
+            redraw = true;
+        }
+        if let Some(ref replay) = replay {
+            events.retain(|event| matches!(event, Event::Quit { .. } | Event::Window { .. }));
+            while next_replay < replay.len() && replay[next_replay].0 <= total_frames {
+                events.push(replay[next_replay].1.clone());
+                next_replay += 1;
+            }
+        }
+        if let Some(ref mut recording) = recording {
+            for line in events.iter().filter_map(format_event) {
+                writeln!(recording, "{} {}", total_frames, line).map_err(|e| e.to_string())?;
+            }
+        }
+
+        for event in events {
+            // The arrow keys and mouse wheel move the camera in and out.
+            let zoom = match event {
+                Event::KeyDown {
+                    keycode: Some(Keycode::Up),
+                    ..
+                } => Some(1. / 1.05),
+                Event::KeyDown {
+                    keycode: Some(Keycode::Down),
+                    ..
+                } => Some(1.05),
+                Event::MouseWheel { y, .. } => Some(1.05f32.powi(-y)),
+                _ => None,
+            };
+            if let Some(zoom) = zoom {
+                target_distance = (target_distance * zoom).max(1.6 * rs);
+                distance_changed = Some(std::time::SystemTime::now());
+                println!("Distance: {}", target_distance);
+            }
+
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    ..
+                } => schwarz.reload_kernel(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::S),
+                    ..
+                } => save_requested = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    ..
+                } => match schwarz.toggle_surface()? {
+                    Some(true) => println!("Showing the surface texture"),
+                    Some(false) => println!("Hiding the surface texture"),
+                    None => println!("No --surface_file to toggle"),
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::P),
+                    ..
+                } => {
+                    paused = !paused;
+                    println!("{}", if paused { "paused" } else { "resumed" });
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::I),
+                    ..
+                } => {
+                    if font.is_some() {
+                        show_info = !show_info;
+                    } else {
+                        println!("No --font to draw the info overlay with");
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Plus),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Equals),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::KpPlus),
+                    ..
+                } => {
+                    schwarz.fov = (schwarz.fov / 1.1).max(1f32.to_radians());
+                    println!("Field of view: {} degrees", schwarz.fov.to_degrees());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Minus),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::KpMinus),
+                    ..
+                } => {
+                    schwarz.fov = (schwarz.fov * 1.1).min(MAX_FOV.to_radians());
+                    println!("Field of view: {} degrees", schwarz.fov.to_degrees());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::LeftBracket),
+                    ..
+                } => {
+                    schwarz.roll -= 5f32.to_radians();
+                    println!("Roll: {} degrees", schwarz.roll.to_degrees());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::RightBracket),
+                    ..
+                } => {
+                    schwarz.roll += 5f32.to_radians();
+                    println!("Roll: {} degrees", schwarz.roll.to_degrees());
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Comma),
+                    ..
+                } => {
+                    schwarz.sky_blend = (schwarz.sky_blend - 0.05).max(0.);
+                    println!("Sky blend: {}", schwarz.sky_blend);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Period),
+                    ..
+                } => {
+                    schwarz.sky_blend = (schwarz.sky_blend + 0.05).min(1.);
+                    println!("Sky blend: {}", schwarz.sky_blend);
+                }
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(w, h),
+                    ..
+                } if frame_dir.is_none() => schwarz.resize(w as u32, h as u32)?,
                 Event::MouseMotion { x, y, .. } => {
-                    cmx = x as f32;
-                    cmy = y as f32;
+                    // Scaled (and flipped) about the middle of the window,
+                    // which turns the view to where it started, so the
+                    // defaults leave the mouse position as it is.
+                    cmx = start_mx + mouse_scale.0 * (x as f32 - center.0);
+                    cmy = start_my + mouse_scale.1 * (y as f32 - center.1);
                 }
                 _ => {}
             }
         }
+
+        if let Some(min_frame_time) = min_frame_time {
+            let elapsed = iteration_start.elapsed().unwrap();
+            if elapsed < min_frame_time {
+                std::thread::sleep(min_frame_time - elapsed);
+            }
+        }
+    }
+
+    let duration = start_time.elapsed().unwrap();
+    println!(
+        "{} frames in {}ms = {} fps average",
+        total_frames,
+        duration.as_millis(),
+        total_frames as f32 / duration.as_secs_f32()
+    );
+
+    if let Some(filename) = matches.value_of("save_state") {
+        let look = mouse_look(mx, my, level_y);
+        let state = CameraState {
+            yaw: look.yaw.to_degrees(),
+            pitch: look.pitch.to_degrees(),
+            roll: schwarz.roll.to_degrees(),
+            distance: target_distance,
+            fov: schwarz.fov.to_degrees(),
+        };
+        write_state(filename, &state)?;
+        println!("Saved the camera to {}", filename);
     }
 
     Ok(())
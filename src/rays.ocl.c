@@ -40,6 +40,60 @@ float null_dt(float r, float dr, float dtheta) {
 __constant int NUM_ITER = 1000000;
 __constant float TS = 0.01;
 
+// Small fudge factor here, because if we get too close, Euler's method can blow up.
+__constant float MIN_R = 2 * GM + 0.0001;
+// Past this (or twice the camera's distance, if that's farther), the direction
+// a ray is going is pretty much the direction it'll keep going.
+__constant float ESCAPE_R = 500.;
+
+float escape_r(float start_r) {
+  return max(ESCAPE_R, 2.f * start_r);
+}
+
+// Position and velocity of a ray, in Schwarzschild coordinates.
+struct ray_t {
+  float r;
+  float theta;
+  float dr;
+  float dtheta;
+};
+
+// A ray leaving the camera, which sits at x=0, z=-start_r looking at the
+// black hole, in the direction (ray_amt, 1) in rectangular coordinates.
+struct ray_t start_ray(float ray_amt, float start_r) {
+  float dx = ray_amt;
+  float dz = 1.;
+
+  struct ray_t ray;
+  ray.r = start_r;
+  ray.theta = M_PI; // We assume we're starting at x=0.
+
+  // Convert to Schwarzschild coordinates
+  ray.dr = -start_r * dz / ray.r;
+  ray.dtheta = - start_r * dx / (ray.r * ray.r);
+  return ray;
+}
+
+// Advance a ray by one time step.
+void step_ray(struct ray_t *ray) {
+  // We're only considering null paths; dt is determined by r, dr, dtheta,
+  // and the condition that the path is null.
+  float dt = null_dt(ray->r, ray->dr, ray->dtheta);
+
+  // For r and theta, on the other hand, we need to solve differential equations.
+  // We're using plain old Euler's method. First, get the second derivatives.
+  float ddr = d2r(ray->r, dt, ray->dr, ray->dtheta);
+  float ddtheta = d2theta(ray->r, ray->dr, ray->dtheta);
+
+  // From those, compute the derivatives.
+  ray->dr += TS * ddr;
+  ray->dtheta += TS * ddtheta;
+
+  // And finally r and theta themselves.
+  ray->r += TS * ray->dr;
+  ray->theta += TS * ray->dtheta;
+}
+
 // The main kernel! Computes the "outcome" for each ray in the given range,
 // which in turn consists of:
 // - whether the ray falls into the black hole, and if so, the angle of the point where
@@ -47,65 +101,69 @@ __constant float TS = 0.01;
 // - whether the ray escapes to infinity, and if so, the angle at which it escapes
 //
 // outcomes[i] = 0 for rays that are captured, and 1 for rays that escape.
+//
+// For drawing things that aren't at infinity (like an accretion disk), we
+// also record how far each ray is from the black hole as it goes around it:
+// radii[i * num_radii + k] is the ray's r once it has swept through k / num_radii
+// of a full turn, or 0 if it was captured or escaped before getting that far.
+// min_radii[i] is the closest the ray ever gets.
 __kernel void gen_outcomes(
                            __global float *angles,
                            __global unsigned char *outcomes,
                            float min,
                            float max,
                            int num,
-                           float start_r
+                           float start_r,
+                           __global float *radii,
+                           int num_radii,
+                           __global float *min_radii
                            ) {
-  // Small fudge factor here, because if we get too close, Euler's method can blow up.
-  float min_r = 2 * GM + 0.0001;
   int slot = get_global_id(0);
   float i = (float)(slot);
 
   // Figure out which way the ith ray actually points (in rectangular coordinates)
   float frac = i / (float)(num - 1);
   float ray_amt = max * frac + min * (1. - frac);
-  float dx = ray_amt;
-  float dz = 1.;
-
-  float r = start_r;
-  float theta = M_PI; // We assume we're starting at x=0.
-
-  // Convert to Schwarzschild coordinates
-  float dr = -start_r * dz / r;
-  float dtheta = - start_r * dx / (r*r);
+  struct ray_t ray = start_ray(ray_amt, start_r);
 
   bool hit = false;
+  __global float *ray_radii = radii + slot * num_radii;
+  int next_radius = 0;
+  float min_r = ray.r;
 
   for (int t = 0; t < NUM_ITER; t++) {
-    // We're only considering null paths; dt is determined by r, dr, dtheta,
-    // and the condition that the path is null.
-    float dt = null_dt(r, dr, dtheta);
-
-    // For r and theta, on the other hand, we need to solve differential equations.
-    // We're using plain old Euler's method. First, get the second derivatives.
-    float ddr = d2r(r, dt, dr, dtheta);
-    float ddtheta = d2theta(r, dr, dtheta);
-
-    // From those, compute the derivatives.
-    dr += TS * ddr;
-    dtheta += TS * ddtheta;
+    // theta starts at pi and goes down as the ray goes around.
+    float swept = M_PI - ray.theta;
+    while (next_radius < num_radii && swept >= next_radius * 2. * M_PI / num_radii) {
+      ray_radii[next_radius++] = ray.r;
+    }
 
-    // And finally r and theta themselves.
-    r += TS * dr;
-    theta += TS * dtheta;
+    step_ray(&ray);
+    min_r = fmin(min_r, ray.r);
 
-    if (r <= min_r) {
+    if (ray.r <= MIN_R) {
       // We've fallen into the black hole.
       hit = true;
       break;
     }
 
-    if (r > 500.) {
+    if (ray.r > escape_r(start_r)) {
       // We're far enough that the direction we're going is pretty much the
       // direction we'll keep going.
       break;
     }
   }
 
+  for (; next_radius < num_radii; next_radius++) {
+    ray_radii[next_radius] = 0.;
+  }
+  min_radii[slot] = min_r;
+
+  float r = ray.r;
+  float theta = ray.theta;
+  float dr = ray.dr;
+  float dtheta = ray.dtheta;
+
   if (hit) {
     // If the ray was captured, what matters is the angle of its position.
     outcomes[slot] = 0;
@@ -121,3 +179,35 @@ __kernel void gen_outcomes(
     angles[slot] = atan2(dz, dx);
   }
 }
+
+// For drawing diagrams: trace rays over the same range as gen_outcomes, but
+// record the path each one takes rather than where it ends up. Every
+// `stride` steps, the ray's position in rectangular coordinates goes into
+// points (x first, then z), num_points per ray. Once a ray has been captured
+// or has escaped, its remaining points repeat its final position.
+__kernel void trace_paths(
+                          __global float *points,
+                          float min,
+                          float max,
+                          int num,
+                          float start_r,
+                          int num_points,
+                          int stride
+                          ) {
+  int slot = get_global_id(0);
+  float frac = (float)(slot) / (float)(num - 1);
+  float ray_amt = max * frac + min * (1. - frac);
+  struct ray_t ray = start_ray(ray_amt, start_r);
+
+  bool done = false;
+  for (int k = 0; k < num_points; k++) {
+    int point = 2 * (slot * num_points + k);
+    points[point] = ray.r * sin(ray.theta);
+    points[point + 1] = ray.r * cos(ray.theta);
+
+    for (int t = 0; t < stride && !done; t++) {
+      step_ray(&ray);
+      done = ray.r <= MIN_R || ray.r > escape_r(start_r);
+    }
+  }
+}
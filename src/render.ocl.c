@@ -10,40 +10,390 @@ __constant sampler_t sampler_const =
   CLK_ADDRESS_REPEAT |
   CLK_FILTER_LINEAR;
 
-Pixel pixel_from_img(image2d_t img, float2 coords) {
-  float4 vals = read_imagef(img, sampler_const, coords) * (float4)(255.);
-
-  Pixel res = {
-               (unsigned char)vals.w,
-               (unsigned char)vals.x,
-               (unsigned char)vals.y,
-               (unsigned char)vals.z
-  };
-  return res;
+// For comparison: each lookup takes the nearest texel, rather than blending
+// the four around it.
+__constant sampler_t nearest_sampler =
+  CLK_NORMALIZED_COORDS_TRUE |
+  CLK_ADDRESS_REPEAT |
+  CLK_FILTER_NEAREST;
+
+// Convert one channel between sRGB-encoded and linear light, both in [0, 1].
+float srgb_to_linear(float c) {
+  return c <= 0.04045f ? c / 12.92f : pow((c + 0.055f) / 1.055f, 2.4f);
+}
+
+float linear_to_srgb(float c) {
+  return c <= 0.0031308f ? c * 12.92f : 1.055f * pow(c, 1.f / 2.4f) - 0.055f;
 }
 
-__constant float max_r = 5.;
+// Colors are worked on as floats in linear light, so that blending and
+// antialiasing add up light the way it actually adds up. Textures come in,
+// and frames go out, sRGB-encoded; with srgb = 0 they're treated as linear
+// instead, which is the old look (darker wherever a lot of sky gets squeezed
+// into one pixel).
+float3 decode_color(float3 c, int srgb) {
+  if (!srgb) {
+    return c;
+  }
+  return (float3)(srgb_to_linear(c.x), srgb_to_linear(c.y), srgb_to_linear(c.z));
+}
+
+float3 encode_color(float3 c, int srgb) {
+  if (!srgb) {
+    return c;
+  }
+  return (float3)(linear_to_srgb(c.x), linear_to_srgb(c.y), linear_to_srgb(c.z));
+}
+
+float3 color_from_img(image2d_t img, float2 coords, int srgb, int filter) {
+  float4 texel = filter ? read_imagef(img, sampler_const, coords)
+                        : read_imagef(img, nearest_sampler, coords);
+  return decode_color(texel.xyz, srgb);
+}
+
+// Bring a color brighter than white back into range with a tone curve,
+// rather than clipping it: mode 1 is Reinhard's c / (1 + c), and mode 2 is
+// Narkowicz's fit to the ACES filmic curve.
+float3 tonemap_color(float3 c, int mode) {
+  if (mode == 1) {
+    return c / (1.f + c);
+  }
+  return clamp(c * (2.51f * c + 0.03f) / (c * (2.43f * c + 0.59f) + 0.14f), 0.f, 1.f);
+}
+
+// Shift a color toward red: f = 0 leaves it alone, and f = 1 leaves only the
+// red.
+float3 redden_color(float3 c, float f) {
+  return c * (float3)(1.f, 1.f - f, (1.f - f) * (1.f - f));
+}
+
+// How much of the pixel at the given location is covered by the ruler
+// overlay: rings every `spacing` radians out from the center of the view,
+// plus the horizontal and vertical lines through the center. Lines are
+// about a pixel wide, with antialiased edges.
+float ruler_coverage(float2 pixel, unsigned int x_res, unsigned int y_res, float spacing,
+                     float screen_scale) {
+  float half_width = (float)(x_res/2);
+  float2 d = pixel - (float2)((float)(x_res)/2., (float)(y_res)/2.);
+  float dist = length(d);
+
+  float line_dist = min(fabs(d.x), fabs(d.y));
+
+  // A ray's angle from the center of the view is atan(r), where r is
+  // computed as in the schwarz kernel below.
+  float angle = atan(screen_scale * dist / half_width);
+  float ring_angle = round(angle / spacing) * spacing;
+  if (ring_angle > 0. && ring_angle < M_PI/2.) {
+    float ring_dist = tan(ring_angle) * half_width / screen_scale;
+    line_dist = min(line_dist, fabs(dist - ring_dist));
+  }
+
+  return 1.f - smoothstep(0.5f, 1.5f, line_dist);
+}
+
+// How much of the pixel at the given location is covered by a ring around
+// the center of the view at tan_angle from it (0 for no ring), about a pixel
+// wide.
+float ring_coverage(float2 pixel, unsigned int x_res, unsigned int y_res, float tan_angle,
+                    float screen_scale) {
+  if (tan_angle <= 0.f) {
+    return 0.f;
+  }
+  float half_width = (float)(x_res/2);
+  float dist = length(pixel - (float2)((float)(x_res)/2., (float)(y_res)/2.));
+  float ring_dist = tan_angle * half_width / screen_scale;
+  return 1.f - smoothstep(0.5f, 1.5f, fabs(dist - ring_dist));
+}
 
 struct res_t {
   float angle;
   unsigned char outcome;
+  float min_r;
 };
 
-struct res_t lookup(__global float *angles, __global unsigned char *angle_results, float pos) {
+// Look up the outcome for a ray at position pos in the table, interpolating
+// between the entries on either side of it. Interpolating across a switch
+// from capture to escape would be meaningless, so there it just takes the
+// lower entry. With interpolate = 0, it takes the nearest entry instead.
+struct res_t lookup(__global float *angles, __global unsigned char *angle_results,
+                    __global float *min_radii, float pos, int interpolate) {
+  struct res_t res;
+  if (!interpolate) {
+    int nearest = (int)(pos + 0.5f);
+    res.outcome = angle_results[nearest];
+    res.angle = angles[nearest];
+    res.min_r = min_radii[nearest];
+    return res;
+  }
+
   int posi = (int)(pos);
   float f = pos - posi;
-  struct res_t res;
 
   res.outcome = angle_results[posi];
   if (res.outcome != angle_results[posi + 1]) {
     res.angle = angles[posi];
+    res.min_r = min_radii[posi];
   } else {
     res.angle = (1.-f) * angles[posi] + f * angles[posi+1];
+    res.min_r = (1.-f) * min_radii[posi] + f * min_radii[posi+1];
   }
 
   return res;
 }
 
+// Whether the stretch of the table from lo to hi crosses between captured
+// and escaping rays, and if so, how much of it is past the crossing (with
+// *edge set to the last entry before it); -1 if it doesn't cross. The
+// crossing is taken to be halfway between the entries either side. Rays are
+// captured up to some angle and escape past it, so there's only one
+// crossing to find.
+float boundary_coverage(__global unsigned char *angle_results, float lo, float hi, int *edge) {
+  int a = (int)(lo + 0.5f);
+  int b = (int)(hi + 0.5f);
+  if (angle_results[a] == angle_results[b]) {
+    return -1.f;
+  }
+  while (b - a > 1) {
+    int mid = (a + b) / 2;
+    if (angle_results[mid] == angle_results[a]) {
+      a = mid;
+    } else {
+      b = mid;
+    }
+  }
+  *edge = a;
+  return clamp((hi - ((float)(a) + 0.5f)) / (hi - lo), 0.f, 1.f);
+}
+
+// Rotate a vector from a ray's plane, where x is across the view and y is
+// along it, to the black hole's frame (in which z is the pole): around the
+// view axis by the pixel's angle from the center of the screen, then by the
+// camera's pitch and yaw.
+float3 to_world(float3 v, float pixel_angle, float x_angle, float y_angle) {
+  v = (float3)(cos(pixel_angle) * v.x + sin(pixel_angle) * v.z,
+               v.y,
+               -sin(pixel_angle) * v.x + cos(pixel_angle) * v.z);
+
+  v = (float3)(v.x,
+               cos(y_angle) * v.y + sin(y_angle) * v.z,
+               -sin(y_angle) * v.y + cos(y_angle) * v.z);
+
+  return (float3)(cos(x_angle) * v.x + sin(x_angle) * v.y,
+                  -sin(x_angle) * v.x + cos(x_angle) * v.y,
+                  v.z);
+}
+
+// Where a ray first crosses the equator between disk_inner and disk_outer, as
+// a distance from the black hole, or 0 if it doesn't. radii are the ray's
+// (see gen_outcomes in rays.ocl.c), and e1z and e2z are how far the x and y
+// axes of the ray's plane point out of the equator. The plane meets the
+// equator along a line through the black hole, so the ray crosses it every
+// half turn; the camera is at -y, so the ray starts a quarter turn behind x.
+// *swept_at is set to how far around the ray had gone when it crossed, and
+// *dr to how fast its r was changing with that angle there.
+float disk_crossing(__global float *radii, unsigned int num_radii,
+                    float e1z, float e2z, float disk_inner, float disk_outer,
+                    float *swept_at, float *dr) {
+  if (e1z == 0.f && e2z == 0.f) {
+    // Edge on.
+    return 0.f;
+  }
+
+  float first = atan2(-e1z, e2z) + M_PI/2.;
+  first -= M_PI * floor(first / M_PI);
+  for (float swept = first; swept < 2. * M_PI; swept += M_PI) {
+    float pos = swept / (2. * M_PI) * (float)(num_radii);
+    int k = (int)(pos);
+    if (k + 1 >= (int)(num_radii) || radii[k] == 0.f || radii[k + 1] == 0.f) {
+      // The ray was captured or escaped before getting here.
+      break;
+    }
+
+    float r = mix(radii[k], radii[k + 1], pos - (float)(k));
+    if (r >= disk_inner && r <= disk_outer) {
+      *swept_at = swept;
+      *dr = (radii[k + 1] - radii[k]) * (float)(num_radii) / (2. * M_PI);
+      return r;
+    }
+  }
+  return 0.f;
+}
+
+// Color of the accretion disk at distance r from the black hole. A thin
+// disk's temperature goes as r^(-3/4) * (1 - sqrt(disk_inner / r))^(1/4),
+// which peaks at about 0.488 * disk_inner^(-3/4) a little way out from the
+// inner edge; hotter parts go from red through yellow to white. (The ramp
+// is in sRGB terms, as a texture would be.)
+//
+// doppler is the Doppler factor of the light, from disk_doppler (1 for no
+// shift): the temperature we see goes up by it, and the brightness by its
+// cube on top of that, since the ramp is for a disk at rest.
+float3 disk_color(float r, float disk_inner, float brightness, int srgb, float doppler) {
+  float x = disk_inner / r;
+  float temp = pow(x, 0.75f) * pow(1.f - sqrt(x), 0.25f) / 0.488f * brightness * doppler;
+  float3 color = clamp((float3)(3.f * temp, 3.f * temp - 1.f, 3.f * temp - 2.f), 0.f, 1.f);
+  return decode_color(color, srgb) * doppler * doppler * doppler;
+}
+
+// Color of the disk at distance r and angle phi around it (anticlockwise
+// from +x, seen from above the north pole) with disktex on it instead of
+// the temperature ramp: across the texture is outward, from disk_inner to
+// disk_outer, and down it is once around. doppler (see disk_color) only
+// changes the brightness, since the texture's colors aren't temperatures.
+float3 disk_texture_color(image2d_t disktex, float r, float phi, float disk_inner,
+                          float disk_outer, float brightness, int srgb, int hdr, int filter,
+                          float doppler) {
+  float2 coords = (float2)((r - disk_inner) / (disk_outer - disk_inner), phi / (2.f * M_PI));
+  // Repeating is right around the disk, but not across it.
+  coords.x = clamp(coords.x, 0.f, 1.f);
+  return color_from_img(disktex, coords, srgb && !hdr, filter)
+    * brightness * doppler * doppler * doppler;
+}
+
+// The Doppler factor (observed over emitted frequency) for light from the
+// disk at distance r, where a ray crosses it at swept_at around its plane
+// with r changing by dr per radian (see disk_crossing), and e1 and e2 are
+// the plane's axes. The disk orbits anticlockwise around the pole at the
+// Keplerian speed a static observer there would measure, sqrt(GM / (r -
+// 2GM)) (in units of c), capped short of light speed for disks inside the
+// photon sphere. This is just the shift from the disk's motion; the
+// gravitational shift on the way out is disk_gravity_shift's.
+float disk_doppler(float r, float swept_at, float dr, float3 e1, float3 e2, float horizon_r) {
+  // The ray started a quarter turn behind e1.
+  float a = swept_at - M_PI/2.;
+  float3 out = cos(a) * e1 + sin(a) * e2;
+  float3 along = -sin(a) * e1 + cos(a) * e2;
+
+  // Which way the traced ray was going there, measured in a static
+  // observer's frame; the light itself goes the other way, to the camera.
+  float3 ray_dir = normalize(dr / sqrt(1.f - horizon_r / r) * out + r * along);
+  float3 light_dir = -ray_dir;
+
+  float3 orbit_dir = normalize((float3)(-out.y, out.x, 0.f));
+  float v = min(sqrt(horizon_r / 2.f / (r - horizon_r)), 0.99f);
+  float gamma = 1.f / sqrt(1.f - v * v);
+  return 1.f / (gamma * (1.f - v * dot(orbit_dir, light_dir)));
+}
+
+// The shift light from a static source at distance r gets on its way out to
+// the camera, which is taken to be far enough out not to shift it back.
+float disk_gravity_shift(float r, float horizon_r) {
+  return sqrt(max(1.f - horizon_r / r, 0.f));
+}
+
+// The frame's spare fourth byte gets the frequency shift (observed over
+// emitted) of the light reaching each pixel, for debugging: 1 (128) for
+// the sky, whose light arrives as it left, 0 for the horizon, which no light
+// climbs out of, and up to 2 (255) for the disk.
+uchar encode_shift(float shift) {
+  return (uchar)(clamp(shift, 0.f, 2.f) * 127.5f + 0.5f);
+}
+
+// The ith number (from 1) of the van der Corput sequence in the given base:
+// i's digits mirrored around the point.
+float radical_inverse(int i, int base) {
+  float inv = 1.f / (float)(base);
+  float f = inv;
+  float result = 0.f;
+  while (i > 0) {
+    result += f * (float)(i % base);
+    i /= base;
+    f *= inv;
+  }
+  return result;
+}
+
+// Where in its pixel antialiasing subsample (aa_x, aa_y) goes, from 0 to 1
+// in each direction:
+// - pattern 0 is a regular aa by aa grid;
+// - pattern 1 is that grid turned by atan(1/2) about the pixel center (and
+//   wrapped back into the pixel), so no two samples share a row or column
+//   and near-horizontal and near-vertical edges get aa*aa distinct steps
+//   instead of aa;
+// - pattern 2 is the first aa*aa points of the Halton sequence in bases 2
+//   and 3, shifted by a per-pixel pseudo-random amount so that neighboring
+//   pixels don't share a pattern. The shift is a hash of the pixel and
+//   seed, so it's the same every frame and every run with the same seed.
+float2 subsample_offset(int aa_x, int aa_y, int aa, int pattern, uint px, uint py, uint seed) {
+  float2 grid = (float2)((float)(aa_x), (float)(aa_y)) / (float)(aa);
+  if (pattern == 1) {
+    float2 c = grid + 0.5f / (float)(aa) - 0.5f;
+    float2 turned = (float2)(2.f * c.x - c.y, c.x + 2.f * c.y) / sqrt(5.f);
+    return turned + 0.5f - floor(turned + 0.5f);
+  }
+  if (pattern == 2) {
+    int i = aa_y * aa + aa_x + 1;
+    uint h = px * 73856093u ^ py * 19349663u ^ seed * 83492791u;
+    h = (h ^ (h >> 13)) * 1274126177u;
+    float2 shift = (float2)((float)(h & 0xffffu), (float)(h >> 16)) / 65536.f;
+    float2 p = (float2)(radical_inverse(i, 2), radical_inverse(i, 3)) + shift;
+    return p - floor(p);
+  }
+  return grid;
+}
+
+// Equirectangular texture coordinates for a direction in the black hole's
+// frame: phi (the texture's v, returned second) goes from 0 at the north pole
+// (+z) to 1 at the south pole, and theta (u) from 0 to 1 eastward around it,
+// starting from -x. For the event horizon, that's a map seen from outside;
+// the sky is seen from inside, so sky_color mirrors it.
+float2 equirect_coords(float3 dir) {
+  return (float2)((atan2(dir.y, dir.x) + M_PI) / (2. * M_PI), acos(dir.z) / M_PI);
+}
+
+// Texture coordinates for a direction in a cubemap whose six square faces
+// are laid side by side, face_size texels each: front (+x), right (-y), back
+// (-x), left (+y), up (+z) and down (-z), each as seen from the middle of
+// the cube, upright (or, for up and down, with the front toward the bottom
+// and top respectively). So the front is the middle of an equirectangular
+// sky, and the faces meet the way they do in the usual cross layout.
+// Coordinates stay half a texel inside their face, so filtering doesn't
+// bleed in the next one.
+float2 cube_coords(float3 d, float face_size) {
+  float3 a = fabs(d);
+  int face;
+  float2 st; // From -1 to 1 across the face, rightward and upward.
+  if (a.x >= a.y && a.x >= a.z) {
+    face = d.x > 0.f ? 0 : 2;
+    st = (float2)(d.x > 0.f ? -d.y : d.y, d.z) / a.x;
+  } else if (a.y >= a.z) {
+    face = d.y < 0.f ? 1 : 3;
+    st = (float2)(d.y < 0.f ? -d.x : d.x, d.z) / a.y;
+  } else {
+    face = d.z > 0.f ? 4 : 5;
+    st = (float2)(-d.y, d.z > 0.f ? -d.x : d.x) / a.z;
+  }
+  float edge = 0.5f / face_size;
+  float2 uv = clamp((float2)(1.f + st.x, 1.f - st.y) / 2.f, edge, 1.f - edge);
+  return (float2)(((float)(face) + uv.x) / 6.f, uv.y);
+}
+
+// Where to sample a sky texture for the direction dir, in the black hole's
+// frame; projection 0 is equirectangular, and 1 a cubemap as in cube_coords.
+float2 sky_coords(image2d_t img, float3 dir, int projection) {
+  if (projection == 1) {
+    return cube_coords(dir, (float)(get_image_height(img)));
+  }
+  // Why -theta here and not for the event horizon? Because we're seeing
+  // the "front" of the event horizon, but the "back" of the skybox.
+  float2 coords = equirect_coords(dir);
+  return (float2)(-coords.x, coords.y);
+}
+
+// The sky in the direction dir, blended between the two sky textures, before
+// any brightness scaling.
+float3 sky_color(image2d_t skytex, image2d_t skytex_b, float3 dir, int projection,
+                 float sky_blend, int srgb, int hdr_textures, int filter) {
+  float3 color = color_from_img(skytex, sky_coords(skytex, dir, projection),
+                                srgb && !(hdr_textures & 1), filter);
+  if (sky_blend > 0.) {
+    float3 color_b = color_from_img(skytex_b, sky_coords(skytex_b, dir, projection),
+                                    srgb && !(hdr_textures & 2), filter);
+    color = mix(color, color_b, sky_blend);
+  }
+  return color;
+}
+
 __kernel void schwarz(
                       __global Pixel *buffer,
                       __global float *angles,
@@ -51,76 +401,688 @@ __kernel void schwarz(
                       unsigned int x_res,
                       unsigned int y_res,
                       unsigned int pitch,
-                      float cx, // mouse location
-                      float cy,
+                      float yaw, // camera direction, in radians (see Look)
+                      float look_pitch,
                       read_only image2d_t skytex,
+                      read_only image2d_t skytex_b,
                       read_only image2d_t spheretex,
                       int aa,
-                      unsigned int num_outcomes
+                      unsigned int num_outcomes,
+                      float sky_blend, // 0 = all skytex, 1 = all skytex_b
+                      float sky_brightness,
+                      float ruler_spacing, // 0 = no ruler
+                      float4 ruler_color,
+                      float surface_rotation, // radians around the poles
+                      __global float *radii,
+                      unsigned int num_radii,
+                      float disk_inner, // disk_outer = 0 means no disk
+                      float disk_outer,
+                      float disk_brightness,
+                      __global float *min_radii,
+                      float redshift, // 0 = no redshift tint
+                      float horizon_r,
+                      float screen_scale, // tan(half the horizontal field of view)
+                      float table_min, // range of rays the outcome table covers
+                      float table_max,
+                      int tonemap, // 0 = none, then as in tonemap_color
+                      float exposure,
+                      int srgb, // 0 = treat textures and output as linear
+                      int hdr_textures, // which textures are already linear floats:
+                                        // 1 = skytex, 2 = skytex_b, 4 = spheretex,
+                                        // 8 = disktex
+                      unsigned int x_offset, // where in each row of buffer this view starts
+                      float eye_angle, // 0 except for stereo; see below
+                      int interpolate, // 0 = nearest outcome, for comparison
+                      int filter, // 0 = nearest texel, likewise
+                      float roll, // radians around the view axis, clockwise
+                      int beaming, // 0 = no Doppler shift on the disk
+                      int aa_pattern, // as in subsample_offset
+                      unsigned int seed, // for aa_pattern's jitter
+                      unsigned int buffer_x, // where in the frame buffer starts,
+                      unsigned int buffer_y, // if it only holds a tile of it
+                      float chromatic, // 0 = lensing bends every color alike
+                      int compare, // 1 = no lensing on the left half, for comparison
+                      int sky_projection, // 0 = equirectangular skies, 1 = cubemaps
+                      float photon_ring, // tan of the angle out to each orbit marker,
+                      float isco_ring, // or 0 for none
+                      float4 photon_ring_color,
+                      float4 isco_ring_color,
+                      read_only image2d_t disktex,
+                      int disk_textured // 0 = the disk gets disk_color's ramp
                       ) {
-  int pixel_loc = get_global_id(0) + get_global_id(1) * pitch;
-  int res_r = 0;
-  int res_g = 0;
-  int res_b = 0;
+  int pixel_loc = x_offset + get_global_id(0) - buffer_x + (get_global_id(1) - buffer_y) * pitch;
+  // With compare, rays on the left half go straight out to the sky, as if
+  // there were no black hole there.
+  bool flat = compare && get_global_id(0) < x_res / 2;
+  float3 sum = (float3)(0.f, 0.f, 0.f);
+  float shift_sum = 0.f;
 
   for (int aa_x = 0; aa_x < aa; aa_x++) {
     for (int aa_y = 0; aa_y < aa; aa_y++) {
       float x = (float)(get_global_id(0));
       float y = (float)(get_global_id(1));
 
-      x += (float)(aa_x) / (float)(aa);
-      y += (float)(aa_y) / (float)(aa);
+      float2 offset = subsample_offset(aa_x, aa_y, aa, aa_pattern,
+                                       get_global_id(0) + x_offset, get_global_id(1), seed);
+      x += offset.x;
+      y += offset.y;
 
       float2 p = (float2)((x - (float)(x_res)/2.) / (float)(x_res/2),
                           (y - (float)(y_res)/2.) / (float)(x_res/2)); // Note: x_res here is not a typo.
                                                                        // We want square pixels.
+      // Rolling the camera clockwise turns everything it sees the other way
+      // around the middle of the screen.
+      p = (float2)(cos(roll) * p.x - sin(roll) * p.y,
+                   sin(roll) * p.x + cos(roll) * p.y);
+
+      // Where the ray crosses the plane one unit in front of the camera.
+      // For a stereo eye, which looks parallel to the middle of the view
+      // rather than straight at the black hole, that's turned by eye_angle
+      // to be relative to the eye's own line to the hole.
+      float2 t = p * screen_scale;
+      if (eye_angle != 0.f) {
+        float2 d = (float2)(cos(eye_angle) * t.x + sin(eye_angle),
+                            cos(eye_angle) - sin(eye_angle) * t.x);
+        t = (float2)(d.x, t.y) / max(d.y, 0.001f);
+      }
+      float r = length(t);
+
+      // Past either end of the table, just use the outcome at that end.
+      float table_scale = (float)(num_outcomes) / (table_max - table_min);
+      float last = (float)(num_outcomes) - 1.001f;
+      float pos = clamp((r - table_min) * table_scale, 0.f, last);
 
-      float r = length(p) * 3.;
-
-      struct res_t lookup_res = lookup(angles, angle_results, r * (float)(num_outcomes) / max_r);
-      float angle_out = lookup_res.angle;
-      unsigned char res = lookup_res.outcome;
-
-      float pixel_angle = atan2(p.y, p.x);
-      // The xy-plane goes through the equator. x is the screen's x; z is the screen's y.
-      float3 loc_rect = (float3)(cos(angle_out), sin(angle_out), 0.);
-      loc_rect = (float3)(cos(pixel_angle) * loc_rect.x + sin(pixel_angle) * loc_rect.z,
-                          loc_rect.y,
-                          -sin(pixel_angle) * loc_rect.x + cos(pixel_angle) * loc_rect.z);
-
-      float x_angle = cx / 200.;
-      float y_angle = (cy - 600.) / 200.;
-
-      loc_rect = (float3)(loc_rect.x,
-                          cos(y_angle) * loc_rect.y + sin(y_angle) * loc_rect.z,
-                          -sin(y_angle) * loc_rect.y + cos(y_angle) * loc_rect.z);
-
-      loc_rect = (float3)(cos(x_angle) * loc_rect.x + sin(x_angle) * loc_rect.y,
-                          -sin(x_angle) * loc_rect.x + cos(x_angle) * loc_rect.y,
-                          loc_rect.z);
-
-      float phi = acos(loc_rect.z) / M_PI;
-      float theta = (atan2(loc_rect.y, loc_rect.x) + M_PI) / (2. * M_PI);
-
-      Pixel pixel;
-      if (res == 0) {
-        pixel = pixel_from_img(spheretex, (float2)(theta, phi));
-      } else {
-        // Why -theta here and not in the other case?
-        // Because we're seeing the "front" of the event horizon, but
-        // the "back" of the skybox.
-        pixel = pixel_from_img(skytex, (float2)(-theta, phi));
+      // How much of the table this subsample covers, give or take (stereo
+      // views stretch it a little), and whether that crosses the edge of
+      // the hole. If it does, both sides get shaded and blended by how much
+      // of the subsample each covers, so the silhouette comes out smooth
+      // instead of each subsample snapping to one side or the other.
+      float footprint = screen_scale / (float)(x_res/2) / (float)(aa) * table_scale;
+      int edge = 0;
+      float coverage = -1.f;
+      if (interpolate && !flat) {
+        coverage = boundary_coverage(angle_results, clamp(pos - footprint / 2.f, 0.f, last),
+                                     clamp(pos + footprint / 2.f, 0.f, last), &edge);
       }
-      res_r += pixel.r;
-      res_g += pixel.g;
-      res_b += pixel.b;
+
+      float pixel_angle = atan2(t.y, t.x);
+      float x_angle = yaw;
+      float y_angle = look_pitch;
+
+      float3 color = (float3)(0.f, 0.f, 0.f);
+      float shift = 0.f;
+      for (int side = 0; side < (coverage < 0.f ? 1 : 2); side++) {
+        float side_pos = pos;
+        float weight = 1.f;
+        if (coverage >= 0.f) {
+          side_pos = side == 0 ? min(pos, (float)(edge)) : max(pos, min((float)(edge + 1), last));
+          weight = side == 0 ? 1.f - coverage : coverage;
+        }
+        struct res_t lookup_res = lookup(angles, angle_results, min_radii, side_pos, interpolate);
+        float angle_out = flat ? atan2(1.f, r) : lookup_res.angle;
+        unsigned char res = flat ? 1 : lookup_res.outcome;
+
+        // The xy-plane goes through the equator. x is the screen's x; z is the screen's y.
+        float3 loc_rect = to_world((float3)(cos(angle_out), sin(angle_out), 0.),
+                                   pixel_angle, x_angle, y_angle);
+
+        float2 coords = equirect_coords(loc_rect);
+        float theta = coords.x;
+        float phi = coords.y;
+
+        float3 side_color;
+        if (res == 0) {
+          side_color = color_from_img(spheretex,
+                                      (float2)(theta + surface_rotation / (2. * M_PI), phi),
+                                      srgb && !(hdr_textures & 4), filter);
+        } else {
+          side_color = sky_color(skytex, skytex_b, loc_rect, sky_projection, sky_blend,
+                                 srgb, hdr_textures, filter);
+          if (chromatic != 0.f) {
+            // For looks only: bend red a little more than the table says
+            // and blue a little less, so the sky fringes like it went
+            // through a prism, most of all around the photon ring. The
+            // deflection is only known modulo a full turn, which doesn't
+            // matter outside the thin rings of rays that loop the hole.
+            float straight = atan2(1.f, r);
+            float deflection = angle_out - straight;
+            deflection -= 2.f * M_PI * round(deflection / (2.f * M_PI));
+            float red_out = straight + deflection * (1.f + chromatic);
+            float blue_out = straight + deflection * (1.f - chromatic);
+            float3 red_dir = to_world((float3)(cos(red_out), sin(red_out), 0.),
+                                      pixel_angle, x_angle, y_angle);
+            float3 blue_dir = to_world((float3)(cos(blue_out), sin(blue_out), 0.),
+                                       pixel_angle, x_angle, y_angle);
+            side_color.x = sky_color(skytex, skytex_b, red_dir, sky_projection, sky_blend,
+                                     srgb, hdr_textures, filter).x;
+            side_color.z = sky_color(skytex, skytex_b, blue_dir, sky_projection, sky_blend,
+                                     srgb, hdr_textures, filter).z;
+          }
+          if (tonemap > 0) {
+            side_color = tonemap_color(side_color * sky_brightness * exposure, tonemap);
+          } else {
+            // Saturate at white.
+            side_color = min(side_color * sky_brightness, 1.f);
+          }
+          if (redshift > 0. && !flat) {
+            // This is the redshift that light from a source sitting where the
+            // ray came closest would have, far away: not physical for light
+            // from the sky, but it shows how deep into the hole's potential
+            // each ray went.
+            float depth = 1.f - sqrt(1.f - horizon_r / lookup_res.min_r);
+            side_color = redden_color(side_color, clamp(redshift * depth, 0.f, 1.f));
+          }
+        }
+        color += weight * side_color;
+        shift += res == 0 ? 0.f : weight;
+      }
+
+      if (disk_outer > 0. && !flat) {
+        // The disk is opaque, so it hides whatever the ray would have hit.
+        int slot = min((int)(pos + 0.5f), (int)(num_outcomes) - 1);
+        float3 e1 = to_world((float3)(1., 0., 0.), pixel_angle, x_angle, y_angle);
+        float3 e2 = to_world((float3)(0., 1., 0.), pixel_angle, x_angle, y_angle);
+        float swept_at, dr;
+        float disk_r = disk_crossing(radii + slot * num_radii, num_radii,
+                                     e1.z, e2.z, disk_inner, disk_outer, &swept_at, &dr);
+        if (disk_r > 0.) {
+          // Beaming and redshift each apply their half of the shift if
+          // they're on, but the debug byte always gets all of it.
+          float motion = disk_doppler(disk_r, swept_at, dr, e1, e2, horizon_r);
+          float gravity = disk_gravity_shift(disk_r, horizon_r);
+          shift = motion * gravity;
+          float doppler = (beaming ? motion : 1.f) * (redshift > 0. ? gravity : 1.f);
+          if (disk_textured) {
+            float a = swept_at - M_PI/2.;
+            float3 at = cos(a) * e1 + sin(a) * e2;
+            color = disk_texture_color(disktex, disk_r, atan2(at.y, at.x), disk_inner,
+                                       disk_outer, disk_brightness, srgb, hdr_textures & 8,
+                                       filter, doppler);
+          } else {
+            color = disk_color(disk_r, disk_inner, disk_brightness, srgb, doppler);
+          }
+        }
+      }
+      sum += color;
+      shift_sum += shift;
     }
   }
 
-  res_r /= (aa*aa);
-  res_g /= (aa*aa);
-  res_b /= (aa*aa);
+  float3 color = sum / (float)(aa*aa);
 
-  Pixel final_pixel = { res_b, res_g, res_r, 0 };
+  if (ruler_spacing > 0.) {
+    float2 pixel = (float2)((float)(get_global_id(0)) + 0.5, (float)(get_global_id(1)) + 0.5);
+    float cov = ruler_coverage(pixel, x_res, y_res, ruler_spacing, screen_scale) * ruler_color.w;
+    color = mix(color, decode_color(ruler_color.xyz, srgb), cov);
+  }
+
+  if (photon_ring > 0.f || isco_ring > 0.f) {
+    float2 pixel = (float2)((float)(get_global_id(0)) + 0.5, (float)(get_global_id(1)) + 0.5);
+    float cov = ring_coverage(pixel, x_res, y_res, photon_ring, screen_scale)
+      * photon_ring_color.w;
+    color = mix(color, decode_color(photon_ring_color.xyz, srgb), cov);
+    cov = ring_coverage(pixel, x_res, y_res, isco_ring, screen_scale) * isco_ring_color.w;
+    color = mix(color, decode_color(isco_ring_color.xyz, srgb), cov);
+  }
+
+  if (compare && get_global_id(0) == x_res / 2) {
+    color = (float3)(1.f, 1.f, 1.f);
+  }
+
+  float3 out = encode_color(clamp(color, 0.f, 1.f), srgb) * 255.f + 0.5f;
+  Pixel final_pixel = { (unsigned char)out.z, (unsigned char)out.y, (unsigned char)out.x,
+                        encode_shift(shift_sum / (float)(aa*aa)) };
   buffer[pixel_loc] = final_pixel;
 }
+
+// Rotating black holes don't have the spherical symmetry the outcome table
+// relies on, so the kerr kernel traces every ray on its own instead: back
+// in time from the camera, through the Kerr metric in Boyer-Lindquist
+// coordinates (r, theta, phi), with theta measured from the spin axis (+z).
+// The equations are Hamilton's, for the Hamiltonian
+//
+//   H = (delta * p_r^2 + p_theta^2 - R / delta - THETA) / (2 * sigma)
+//
+// where, for a photon with energy 1, angular momentum b about the axis and
+// Carter constant q,
+//
+//   sigma = r^2 + a^2 cos^2(theta)      delta = r^2 - 2 m r + a^2
+//   P = r^2 + a^2 - a b                 R = P^2 - delta ((b - a)^2 + q)
+//   THETA = q + a^2 cos^2(theta) - b^2 cot^2(theta)
+//
+// (see James et al., "Gravitational lensing by spinning black holes in
+// astrophysics, and in the movie Interstellar", 2015). b and q stay the
+// same along the ray, so only r, theta, phi and their momenta change.
+struct kerr_ray {
+  float r;
+  float theta;
+  float phi;
+  float p_r;
+  float p_theta;
+};
+
+__constant int KERR_MAX_STEPS = 20000;
+
+// How fast each part of a ray's state changes.
+struct kerr_ray kerr_derivs(struct kerr_ray s, float a, float m, float b, float q) {
+  float sin_t = sin(s.theta);
+  float cos_t = cos(s.theta);
+  // Only rays with b = 0 can reach the poles, but rounding can get others
+  // close enough to divide by nearly nothing.
+  if (fabs(sin_t) < 1e-4f) {
+    sin_t = sin_t < 0.f ? -1e-4f : 1e-4f;
+  }
+  float cot_t = cos_t / sin_t;
+
+  float r2 = s.r * s.r;
+  float sigma = r2 + a * a * cos_t * cos_t;
+  float delta = r2 - 2.f * m * s.r + a * a;
+  float d_delta = 2.f * s.r - 2.f * m;
+  float p = r2 + a * a - a * b;
+  float k = (b - a) * (b - a) + q;
+  float big_r = p * p - delta * k;
+  float d_big_r = 4.f * s.r * p - d_delta * k;
+  float big_theta = q + a * a * cos_t * cos_t - b * b * cot_t * cot_t;
+  float d_big_theta = 2.f * cos_t * sin_t * (b * b / (sin_t * sin_t * sin_t * sin_t) - a * a);
+
+  // H should be 0 for light, but rounding drifts it; keeping its terms in
+  // the momenta' derivatives stops that from feeding on itself as quickly.
+  float h = (delta * s.p_r * s.p_r + s.p_theta * s.p_theta - big_r / delta - big_theta)
+    / (2.f * sigma);
+
+  struct kerr_ray d;
+  d.r = delta * s.p_r / sigma;
+  d.theta = s.p_theta / sigma;
+  d.phi = (a * p / delta + b - a + b * cot_t * cot_t) / sigma;
+  d.p_r = (d_big_r / delta - big_r * d_delta / (delta * delta) - d_delta * s.p_r * s.p_r)
+    / (2.f * sigma) + h * 2.f * s.r / sigma;
+  d.p_theta = d_big_theta / (2.f * sigma) - h * 2.f * a * a * cos_t * sin_t / sigma;
+  return d;
+}
+
+struct kerr_ray kerr_add(struct kerr_ray s, struct kerr_ray d, float h) {
+  s.r += h * d.r;
+  s.theta += h * d.theta;
+  s.phi += h * d.phi;
+  s.p_r += h * d.p_r;
+  s.p_theta += h * d.p_theta;
+  return s;
+}
+
+// One fourth-order Runge-Kutta step of h (negative, to go back in time).
+struct kerr_ray kerr_step(struct kerr_ray s, float h, float a, float m, float b, float q) {
+  struct kerr_ray k1 = kerr_derivs(s, a, m, b, q);
+  struct kerr_ray k2 = kerr_derivs(kerr_add(s, k1, h / 2.f), a, m, b, q);
+  struct kerr_ray k3 = kerr_derivs(kerr_add(s, k2, h / 2.f), a, m, b, q);
+  struct kerr_ray k4 = kerr_derivs(kerr_add(s, k3, h), a, m, b, q);
+  s.r += h / 6.f * (k1.r + 2.f * k2.r + 2.f * k3.r + k4.r);
+  s.theta += h / 6.f * (k1.theta + 2.f * k2.theta + 2.f * k3.theta + k4.theta);
+  s.phi += h / 6.f * (k1.phi + 2.f * k2.phi + 2.f * k3.phi + k4.phi);
+  s.p_r += h / 6.f * (k1.p_r + 2.f * k2.p_r + 2.f * k3.p_r + k4.p_r);
+  s.p_theta += h / 6.f * (k1.p_theta + 2.f * k2.p_theta + 2.f * k3.p_theta + k4.p_theta);
+  return s;
+}
+
+// Where a point in Boyer-Lindquist coordinates is in the black hole's frame.
+float3 kerr_to_rect(float r, float theta, float phi, float a) {
+  float rho = sqrt(r * r + a * a);
+  return (float3)(rho * sin(theta) * cos(phi), rho * sin(theta) * sin(phi), r * cos(theta));
+}
+
+// The radius of the innermost stable circular orbit (Bardeen, Press and
+// Teukolsky) for something orbiting anticlockwise, as the disk does: 6m
+// with no spin, down toward m as a goes to m, and up toward 9m as a goes to
+// -m, where the orbit is against the spin.
+float kerr_isco(float a, float m) {
+  float chi = a / m;
+  float z1 = 1.f + cbrt(1.f - chi * chi) * (cbrt(1.f + chi) + cbrt(1.f - chi));
+  float z2 = sqrt(3.f * chi * chi + z1 * z1);
+  return m * (3.f + z2 - sign(chi) * sqrt((3.f - z1) * (3.f + z1 + 2.f * z2)));
+}
+
+// The color of the light reaching a camera at pos (in the black hole's
+// frame, with Boyer-Lindquist radius camera_r) from the direction dir: the
+// sky where the traced ray escapes, the event horizon where it falls in,
+// or the disk where it first crosses the equator between disk_inner and
+// disk_outer. dir is as seen by an observer there who's being carried
+// around by the hole's spin, at the same rate as the space around them
+// (the "zero angular momentum" observer), which is what makes the view
+// lopsided even looking straight at the hole. The sky's colors are
+// returned before brightness scaling and tone mapping; *escaped says
+// whether it was the sky, and *shift gets the light's frequency shift (as
+// for encode_shift; the disk orbits anticlockwise, as in schwarz).
+float3 kerr_trace(float3 pos, float3 dir, float camera_r, float a, float m, float escape,
+                  float disk_inner, float disk_outer, float disk_brightness,
+                  read_only image2d_t skytex, read_only image2d_t skytex_b,
+                  read_only image2d_t spheretex, read_only image2d_t disktex,
+                  int disk_textured, float sky_blend, float surface_rotation,
+                  int sky_projection, int srgb, int hdr_textures, int filter,
+                  bool *escaped, float *shift) {
+  // The camera's spot in Boyer-Lindquist coordinates, and the directions of
+  // increasing r, theta and phi there.
+  float theta = acos(clamp(pos.z / length(pos), -1.f, 1.f));
+  float phi = atan2(pos.y, pos.x);
+  float3 e_r = (float3)(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+  float3 e_theta = (float3)(cos(theta) * cos(phi), cos(theta) * sin(phi), -sin(theta));
+  float3 e_phi = (float3)(-sin(phi), cos(phi), 0.f);
+
+  // The light's coming toward the camera, so it's going the other way.
+  float3 n = -(float3)(dot(dir, e_r), dot(dir, e_theta), dot(dir, e_phi));
+
+  // Turn that into momenta for light with energy 1 at infinity, from the
+  // local observer's frame.
+  float r = camera_r;
+  float sin_t = sin(theta);
+  float cos_t = cos(theta);
+  float sigma = r * r + a * a * cos_t * cos_t;
+  float delta = r * r - 2.f * m * r + a * a;
+  float big_sigma = sqrt((r * r + a * a) * (r * r + a * a) - a * a * delta * sin_t * sin_t);
+  float rho = sqrt(sigma);
+  float alpha = rho * sqrt(delta) / big_sigma;
+  float omega = 2.f * a * m * r / (big_sigma * big_sigma);
+  float varpi = big_sigma * sin_t / rho;
+  float e_local = 1.f / (alpha + omega * varpi * n.z);
+
+  struct kerr_ray s;
+  s.r = r;
+  s.theta = theta;
+  s.phi = phi;
+  s.p_r = e_local * rho / sqrt(delta) * n.x;
+  s.p_theta = e_local * rho * n.y;
+  float b = e_local * varpi * n.z;
+  float q = s.p_theta * s.p_theta
+    + cos_t * cos_t * (b * b / max(sin_t * sin_t, 1e-8f) - a * a);
+
+  float horizon = m + sqrt(m * m - a * a);
+  float isco = kerr_isco(a, m);
+  *escaped = false;
+  *shift = 0.f;
+  for (int i = 0; i < KERR_MAX_STEPS; i++) {
+    // Small steps close to the horizon, where things change quickly, and
+    // big ones far out, where they don't.
+    float h = -(0.02f * (s.r - horizon) + 0.001f * m);
+    struct kerr_ray next = kerr_step(s, h, a, m, b, q);
+
+    if (disk_outer > 0.f && cos(s.theta) * cos(next.theta) < 0.f) {
+      float f = cos(s.theta) / (cos(s.theta) - cos(next.theta));
+      float disk_r = mix(s.r, next.r, f);
+      if (disk_r >= disk_inner && disk_r <= disk_outer) {
+        // A circular orbit's angular velocity and time dilation, for the
+        // shift 1 / (u^t (1 - omega b)). Inside the innermost stable
+        // orbit there's nothing orbiting to shift, so call it 0. (Circular
+        // orbits go on inward to the photon orbit, where orbit below
+        // reaches 0, but they're unstable, so a disk wouldn't last there.)
+        if (disk_r >= isco) {
+          float sm = sqrt(m);
+          float r32 = disk_r * sqrt(disk_r);
+          float orbit = r32 - 3.f * m * sqrt(disk_r) + 2.f * a * sm;
+          float disk_omega = sm / (r32 + a * sm);
+          float u_t = (r32 + a * sm) / (sqrt(r32) * sqrt(orbit));
+          *shift = 1.f / (u_t * (1.f - disk_omega * b));
+        }
+        if (disk_textured) {
+          return disk_texture_color(disktex, disk_r, mix(s.phi, next.phi, f), disk_inner,
+                                    disk_outer, disk_brightness, srgb, hdr_textures & 8,
+                                    filter, 1.f);
+        }
+        return disk_color(disk_r, disk_inner, disk_brightness, srgb, 1.f);
+      }
+    }
+
+    if (next.r < horizon + 0.01f * m) {
+      // Phi winds around faster and faster toward the horizon, so the
+      // texture comes out swirled; that's the coordinates, not the view.
+      float3 at = (float3)(sin(next.theta) * cos(next.phi), sin(next.theta) * sin(next.phi),
+                           cos(next.theta));
+      float2 coords = equirect_coords(at);
+      return color_from_img(spheretex,
+                            (float2)(coords.x + surface_rotation / (2. * M_PI), coords.y),
+                            srgb && !(hdr_textures & 4), filter);
+    }
+
+    if (next.r > escape) {
+      float3 out = normalize(kerr_to_rect(next.r, next.theta, next.phi, a)
+                             - kerr_to_rect(s.r, s.theta, s.phi, a));
+      *escaped = true;
+      *shift = 1.f;
+      return sky_color(skytex, skytex_b, out, sky_projection, sky_blend, srgb, hdr_textures,
+                       filter);
+    }
+    s = next;
+  }
+
+  // Stuck going around and around the hole.
+  return (float3)(0.f, 0.f, 0.f);
+}
+
+// Render a view of a rotating black hole with spin a (as a fraction of its
+// mass, m = horizon_r / 2), from start_r away. This takes the same
+// arguments as schwarz, so that Schwarz can bind either one, plus those
+// two; the ones it has no use for are ignored. disk_inner and disk_outer
+// are in Boyer-Lindquist r, and the disk doesn't get beaming.
+__kernel void kerr(
+                   __global Pixel *buffer,
+                   __global float *angles,
+                   __global unsigned char *angle_results,
+                   unsigned int x_res,
+                   unsigned int y_res,
+                   unsigned int pitch,
+                   float yaw,
+                   float look_pitch,
+                   read_only image2d_t skytex,
+                   read_only image2d_t skytex_b,
+                   read_only image2d_t spheretex,
+                   int aa,
+                   unsigned int num_outcomes,
+                   float sky_blend,
+                   float sky_brightness,
+                   float ruler_spacing,
+                   float4 ruler_color,
+                   float surface_rotation,
+                   __global float *radii,
+                   unsigned int num_radii,
+                   float disk_inner,
+                   float disk_outer,
+                   float disk_brightness,
+                   __global float *min_radii,
+                   float redshift,
+                   float horizon_r,
+                   float screen_scale,
+                   float table_min,
+                   float table_max,
+                   int tonemap,
+                   float exposure,
+                   int srgb,
+                   int hdr_textures,
+                   unsigned int x_offset,
+                   float eye_angle,
+                   int interpolate,
+                   int filter,
+                   float roll,
+                   int beaming,
+                   int aa_pattern,
+                   unsigned int seed,
+                   unsigned int buffer_x,
+                   unsigned int buffer_y,
+                   float chromatic,
+                   int compare,
+                   int sky_projection,
+                   float photon_ring,
+                   float isco_ring,
+                   float4 photon_ring_color,
+                   float4 isco_ring_color,
+                   read_only image2d_t disktex,
+                   int disk_textured,
+                   float spin,
+                   float start_r
+                   ) {
+  int pixel_loc = x_offset + get_global_id(0) - buffer_x + (get_global_id(1) - buffer_y) * pitch;
+  float m = horizon_r / 2.f;
+  float a = spin * m;
+  float escape = max(500.f, 2.f * start_r);
+
+  // The camera sits on the far side of the hole from where it's looking
+  // (at -y before turning), as in schwarz; here the spin axis doesn't turn
+  // with it, so where it sits matters.
+  float3 pos = to_world((float3)(0.f, -start_r, 0.f), 0.f, yaw, look_pitch);
+  float3 sum = (float3)(0.f, 0.f, 0.f);
+  float shift_sum = 0.f;
+
+  for (int aa_x = 0; aa_x < aa; aa_x++) {
+    for (int aa_y = 0; aa_y < aa; aa_y++) {
+      float2 offset = subsample_offset(aa_x, aa_y, aa, aa_pattern,
+                                       get_global_id(0) + x_offset, get_global_id(1), seed);
+      float x = (float)(get_global_id(0)) + offset.x;
+      float y = (float)(get_global_id(1)) + offset.y;
+
+      float2 p = (float2)((x - (float)(x_res)/2.) / (float)(x_res/2),
+                          (y - (float)(y_res)/2.) / (float)(x_res/2));
+      p = (float2)(cos(roll) * p.x - sin(roll) * p.y,
+                   sin(roll) * p.x + cos(roll) * p.y);
+      float2 t = p * screen_scale;
+
+      // Screen x is the camera's x, and screen y (downward) its -z, as with
+      // to_world's ray planes in schwarz.
+      float3 dir = normalize(to_world((float3)(t.x, 1.f, -t.y), 0.f, yaw, look_pitch));
+
+      bool escaped;
+      float shift;
+      float3 color = kerr_trace(pos, dir, start_r, a, m, escape, disk_inner, disk_outer,
+                                disk_brightness, skytex, skytex_b, spheretex, disktex,
+                                disk_textured, sky_blend,
+                                surface_rotation, sky_projection, srgb, hdr_textures, filter,
+                                &escaped, &shift);
+      shift_sum += shift;
+      if (escaped) {
+        if (tonemap > 0) {
+          color = tonemap_color(color * sky_brightness * exposure, tonemap);
+        } else {
+          color = min(color * sky_brightness, 1.f);
+        }
+      }
+      sum += color;
+    }
+  }
+
+  float3 color = sum / (float)(aa*aa);
+
+  if (ruler_spacing > 0.) {
+    float2 pixel = (float2)((float)(get_global_id(0)) + 0.5, (float)(get_global_id(1)) + 0.5);
+    float cov = ruler_coverage(pixel, x_res, y_res, ruler_spacing, screen_scale) * ruler_color.w;
+    color = mix(color, decode_color(ruler_color.xyz, srgb), cov);
+  }
+
+  float3 out = encode_color(clamp(color, 0.f, 1.f), srgb) * 255.f + 0.5f;
+  Pixel final_pixel = { (unsigned char)out.z, (unsigned char)out.y, (unsigned char)out.x,
+                        encode_shift(shift_sum / (float)(aa*aa)) };
+  buffer[pixel_loc] = final_pixel;
+}
+
+// Post-process for a cheap "damaged lens" look, which has nothing to do with
+// the physics: the red and blue channels are sampled slightly outward and
+// inward from each pixel, so colors fringe more and more toward the edges
+// of the frame. Pixels are laid out the way schwarz writes them, i.e. blue,
+// green, red.
+__kernel void chromatic_aberration(
+                                   __global uchar4 *src,
+                                   __global uchar4 *dest,
+                                   unsigned int x_res,
+                                   unsigned int y_res,
+                                   unsigned int pitch,
+                                   float amount
+                                   ) {
+  int x = get_global_id(0);
+  int y = get_global_id(1);
+  float2 center = (float2)((float)(x_res)/2., (float)(y_res)/2.);
+  float2 d = (float2)((float)(x), (float)(y)) - center;
+
+  int2 red = convert_int2(center + d * (1.f + amount));
+  int2 blue = convert_int2(center + d * (1.f - amount));
+  red = clamp(red, (int2)(0, 0), (int2)((int)(x_res) - 1, (int)(y_res) - 1));
+  blue = clamp(blue, (int2)(0, 0), (int2)((int)(x_res) - 1, (int)(y_res) - 1));
+
+  uchar4 pixel = src[x + y * pitch];
+  pixel.x = src[blue.x + blue.y * pitch].x;
+  pixel.z = src[red.x + red.y * pitch].z;
+  dest[x + y * pitch] = pixel;
+}
+
+// Bloom post-process: light above a threshold bleeds into the pixels around
+// it, which mostly picks out the photon ring and the disk. It takes three
+// passes over the frame. bloom_bright_x keeps just the part of each pixel
+// above threshold (in linear light, and rescaled to fill the byte) and
+// blurs it horizontally, bloom_blur_y blurs that vertically, and bloom_add
+// adds the result back onto the frame in place. The blur is a Gaussian
+// reaching out radius pixels either way.
+float bloom_weight(int d, int radius) {
+  float sigma = max((float)(radius) / 2.f, 0.5f);
+  return exp(-(float)(d * d) / (2.f * sigma * sigma));
+}
+
+__kernel void bloom_bright_x(
+                             __global uchar4 *src,
+                             __global uchar4 *dest,
+                             unsigned int x_res,
+                             unsigned int y_res,
+                             unsigned int pitch,
+                             float threshold,
+                             int radius,
+                             int srgb
+                             ) {
+  int x = get_global_id(0);
+  int y = get_global_id(1);
+
+  float3 sum = (float3)(0.f, 0.f, 0.f);
+  float total = 0.f;
+  for (int d = -radius; d <= radius; d++) {
+    int sx = clamp(x + d, 0, (int)(x_res) - 1);
+    float3 c = decode_color(convert_float3(src[sx + y * pitch].xyz) / 255.f, srgb);
+    float w = bloom_weight(d, radius);
+    sum += w * max(c - threshold, 0.f) / (1.f - threshold);
+    total += w;
+  }
+  dest[x + y * pitch] = (uchar4)(convert_uchar3_sat_rte(sum / total * 255.f), (uchar)0);
+}
+
+__kernel void bloom_blur_y(
+                           __global uchar4 *src,
+                           __global uchar4 *dest,
+                           unsigned int x_res,
+                           unsigned int y_res,
+                           unsigned int pitch,
+                           int radius
+                           ) {
+  int x = get_global_id(0);
+  int y = get_global_id(1);
+
+  float3 sum = (float3)(0.f, 0.f, 0.f);
+  float total = 0.f;
+  for (int d = -radius; d <= radius; d++) {
+    int sy = clamp(y + d, 0, (int)(y_res) - 1);
+    float w = bloom_weight(d, radius);
+    sum += w * convert_float3(src[x + sy * pitch].xyz);
+    total += w;
+  }
+  dest[x + y * pitch] = (uchar4)(convert_uchar3_sat_rte(sum / total), (uchar)0);
+}
+
+__kernel void bloom_add(
+                        __global uchar4 *bloom,
+                        __global uchar4 *dest,
+                        unsigned int x_res,
+                        unsigned int y_res,
+                        unsigned int pitch,
+                        float threshold,
+                        float strength,
+                        int srgb
+                        ) {
+  int x = get_global_id(0);
+  int y = get_global_id(1);
+  uchar4 pixel = dest[x + y * pitch];
+
+  float3 c = decode_color(convert_float3(pixel.xyz) / 255.f, srgb);
+  float3 glow = convert_float3(bloom[x + y * pitch].xyz) / 255.f * (1.f - threshold);
+  c = encode_color(min(c + strength * glow, 1.f), srgb);
+  pixel.xyz = convert_uchar3_sat_rte(c * 255.f);
+  dest[x + y * pitch] = pixel;
+}
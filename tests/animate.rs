@@ -0,0 +1,129 @@
+// A short --animate run through the blackhole_ocl binary, checked against
+// the hashes in tests/animation_hashes.txt so that animations come out the
+// same on every run and every machine. It needs an OpenCL device, so it's
+// ignored unless the gpu_tests feature is on, and does nothing if there
+// isn't one after all.
+//
+// GPUs can round differently from each other, so each channel is rounded to
+// a multiple of 16 before hashing. If the frames are meant to change, record
+// new hashes by running it with BLESS_ANIMATION_HASHES=1.
+
+use std::path::{Path, PathBuf};
+
+// One turn around the black hole, rising above the equator and zooming in,
+// at a constant distance so there's only the one outcome table to generate.
+const CAMERA_PATH: &str = "
+[[keyframe]]
+time = 0
+yaw = 0
+pitch = 0
+roll = 0
+distance = 5
+fov = 90
+
+[[keyframe]]
+time = 1
+yaw = 360
+pitch = 20
+roll = 10
+distance = 5
+fov = 60
+";
+
+const FRAME_COUNT: u32 = 4;
+
+fn hashes_file() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/animation_hashes.txt")
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("blackhole_ocl_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// FNV-1a over every channel of the frame, rounded to the nearest multiple
+// of 16.
+fn frame_hash(path: &Path) -> u64 {
+    let frame = image::open(path).unwrap().to_rgba();
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &v in frame.iter() {
+        hash ^= u64::from(((u32::from(v) + 8) / 16) as u8);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+// Render the animation into a fresh directory with jittered antialiasing
+// and a fixed seed, and hash each frame.
+fn render_animation(dir: &Path, sky: &Path, path: &Path) -> Vec<u64> {
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_blackhole_ocl"))
+        .arg("--animate")
+        .arg(path)
+        .arg("--frames")
+        .arg(dir)
+        .arg("--frame_count")
+        .arg(FRAME_COUNT.to_string())
+        .arg("--sky_file")
+        .arg(sky)
+        .args(["--width", "160", "--height", "120", "--antialias", "2"])
+        .args(["--aa_pattern", "jittered", "--seed", "7"])
+        .arg("--no_cache")
+        .status()
+        .unwrap();
+    assert!(status.success(), "blackhole_ocl exited with {}", status);
+
+    (1..=FRAME_COUNT)
+        .map(|i| frame_hash(&dir.join(format!("frame_{:05}.png", i))))
+        .collect()
+}
+
+#[test]
+#[cfg_attr(not(feature = "gpu_tests"), ignore)]
+fn animation_matches_recorded_hashes() {
+    if let Err(err) = blackhole_ocl::select_device(None, None) {
+        println!("Skipping: no OpenCL device to render with ({})", err);
+        return;
+    }
+    let dir = scratch_dir("animate");
+    // A sky with something in it to bend: a gradient across and down.
+    let sky = dir.join("sky.png");
+    image::RgbImage::from_fn(256, 128, |x, y| image::Rgb([x as u8, (y * 2) as u8, 128]))
+        .save(&sky)
+        .unwrap();
+    let path = dir.join("path.toml");
+    std::fs::write(&path, CAMERA_PATH).unwrap();
+
+    let first = render_animation(&scratch_dir("animate_a"), &sky, &path);
+    let second = render_animation(&scratch_dir("animate_b"), &sky, &path);
+    assert_eq!(first, second, "two runs on the same device differ");
+
+    if std::env::var_os("BLESS_ANIMATION_HASHES").is_some() {
+        let mut contents = String::from(HASHES_HEADER);
+        for hash in &first {
+            contents.push_str(&format!("{:016x}\n", hash));
+        }
+        std::fs::write(hashes_file(), contents).unwrap();
+        return;
+    }
+
+    let recorded: Vec<u64> = std::fs::read_to_string(hashes_file())
+        .unwrap()
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| u64::from_str_radix(line, 16).unwrap())
+        .collect();
+    assert!(
+        !recorded.is_empty(),
+        "{} has no hashes yet; record them with BLESS_ANIMATION_HASHES=1",
+        hashes_file().display()
+    );
+    let got: Vec<String> = first.iter().map(|h| format!("{:016x}", h)).collect();
+    let want: Vec<String> = recorded.iter().map(|h| format!("{:016x}", h)).collect();
+    assert_eq!(got, want);
+}
+
+const HASHES_HEADER: &str = "\
+# Rounded hashes of the frames tests/animate.rs renders, one per line.
+# Rerun it with BLESS_ANIMATION_HASHES=1 to record new ones.
+";
@@ -0,0 +1,113 @@
+// Rendering through the library alone, as a front end other than the
+// blackhole_ocl binary would. These need an OpenCL device, and use the
+// default one, so they're ignored unless the gpu_tests feature is on.
+
+use blackhole_ocl::{select_device, ClConfig, Look, Options, PixelFormat, Schwarz, GM};
+
+fn test_cl() -> ClConfig {
+    let (platform, device) = select_device(None, None).unwrap();
+    ClConfig {
+        platform,
+        device,
+        options: String::new(),
+        kernel_dir: None,
+    }
+}
+
+// A plain grey sky, so anything black in a frame is the event horizon.
+fn write_sky() -> String {
+    let path =
+        std::env::temp_dir().join(format!("blackhole_ocl_test_sky_{}.png", std::process::id()));
+    let sky = image::RgbImage::from_pixel(64, 32, image::Rgb([128, 128, 128]));
+    sky.save(&path).unwrap();
+    path.display().to_string()
+}
+
+// The command line's defaults, with no antialiasing or outcome cache.
+fn options<'a>(x_res: u32, y_res: u32, sky: &'a str, cl: &'a ClConfig) -> Options<'a> {
+    Options {
+        aa: 1,
+        cache_outcomes: false,
+        ..Options::new(sky, x_res, y_res, cl)
+    }
+}
+
+const LEVEL: Look = Look { yaw: 0., pitch: 0. };
+
+#[test]
+#[cfg_attr(not(feature = "gpu_tests"), ignore)]
+fn renders_a_frame_into_a_vec() {
+    let (x_res, y_res) = (160, 120);
+    let (sky, cl) = (write_sky(), test_cl());
+    let schwarz = Schwarz::new(&options(x_res, y_res, &sky, &cl), &mut |_| true).unwrap();
+
+    let mut frame = vec![0u8; (x_res * y_res * 4) as usize];
+    schwarz
+        .render(
+            &mut frame,
+            x_res,
+            y_res,
+            x_res * 4,
+            PixelFormat::BGRX,
+            LEVEL,
+        )
+        .unwrap();
+
+    // Looking straight at the hole, the middle of the frame is the horizon,
+    // and the corners are sky.
+    let pixel = |x: u32, y: u32| {
+        let i = ((y * x_res + x) * 4) as usize;
+        [frame[i], frame[i + 1], frame[i + 2]]
+    };
+    assert_eq!(pixel(x_res / 2, y_res / 2), [0, 0, 0]);
+    assert_ne!(pixel(0, 0), [0, 0, 0]);
+    assert_ne!(pixel(x_res - 1, y_res - 1), [0, 0, 0]);
+}
+
+// How many pixels out from the middle of a level view the horizon's shadow
+// reaches, along the row and the column through the middle.
+fn horizon_radii(x_res: u32, y_res: u32) -> (f32, f32) {
+    let (sky, cl) = (write_sky(), test_cl());
+    let schwarz = Schwarz::new(&options(x_res, y_res, &sky, &cl), &mut |_| true).unwrap();
+    let frame = schwarz.render_image(x_res, y_res, LEVEL).unwrap();
+
+    let black = |x: u32, y: u32| {
+        let i = ((y * x_res + x) * 4) as usize;
+        frame[i..i + 3] == [0, 0, 0]
+    };
+    let across = (0..x_res).filter(|&x| black(x, y_res / 2)).count();
+    let down = (0..y_res).filter(|&y| black(x_res / 2, y)).count();
+    (across as f32 / 2., down as f32 / 2.)
+}
+
+// The shadow is every ray with an impact parameter under 3 sqrt(3) GM, so
+// its edge is the ray that leaves the camera with that, at this tangent
+// from the line of sight; the screen is tan(fov / 2), which is 3 for
+// options' fov, across each half of its width. Pixels are square, so it's a
+// circle at any aspect ratio.
+fn expected_horizon_radius(x_res: u32) -> f32 {
+    let (start_r, critical) = (10. * GM, 27f32.sqrt() * GM);
+    let q = 1. - 2. * GM / start_r;
+    let edge = critical / (start_r * start_r - critical * critical * q).sqrt();
+    edge / 3. * x_res as f32 / 2.
+}
+
+#[test]
+#[cfg_attr(not(feature = "gpu_tests"), ignore)]
+fn horizon_is_round_at_any_aspect_ratio() {
+    for &(x_res, y_res) in &[(320, 320), (320, 180)] {
+        let expected = expected_horizon_radius(x_res);
+        let (across, down) = horizon_radii(x_res, y_res);
+        for &radius in &[across, down] {
+            assert!(
+                (radius - expected).abs() <= 1.5,
+                "at {}x{}, the horizon's radius is {} pixels across and {} down, not {}",
+                x_res,
+                y_res,
+                across,
+                down,
+                expected
+            );
+        }
+    }
+}